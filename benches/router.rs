@@ -0,0 +1,108 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compares `Router::route` against the `route_recognizer` lookup it replaced.
+
+use blacktea::router::Router;
+use blacktea::{HttpResponse, Method};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use hyper::{Body, Request};
+use route_recognizer::{Params, Router as Recognizer};
+
+async fn handler() -> HttpResponse {
+    HttpResponse::Ok().empty()
+}
+
+/// Five routes per resource, mixing static, param and wildcard segments.
+fn patterns(resources: usize) -> Vec<String> {
+    (0..resources)
+        .flat_map(|i| {
+            vec![
+                format!("/api/v1/resource{}", i),
+                format!("/api/v1/resource{}/:id", i),
+                format!("/api/v1/resource{}/:id/items", i),
+                format!("/api/v1/resource{}/:id/items/:item", i),
+                format!("/static/resource{}/*path", i),
+            ]
+        })
+        .collect()
+}
+
+fn paths(resources: usize) -> Vec<String> {
+    (0..resources)
+        .step_by((resources / 20).max(1))
+        .flat_map(|i| {
+            vec![
+                format!("/api/v1/resource{}", i),
+                format!("/api/v1/resource{}/42", i),
+                format!("/api/v1/resource{}/42/items/7", i),
+                format!("/static/resource{}/css/site.css", i),
+                format!("/api/v1/resource{}/42/missing", i),
+            ]
+        })
+        .collect()
+}
+
+fn bench_route(c: &mut Criterion) {
+    let mut group = c.benchmark_group("route");
+    for resources in [20, 200, 2000].iter().copied() {
+        let patterns = patterns(resources);
+        let paths = paths(resources);
+        let routes = patterns.len();
+
+        let mut router = Router::new();
+        for pattern in &patterns {
+            router.add(pattern, Method::GET, handler);
+        }
+        let requests: Vec<_> = paths
+            .iter()
+            .map(|p| Request::get(p.as_str()).body(Body::empty()).unwrap())
+            .collect();
+        group.bench_with_input(
+            BenchmarkId::new("radix_tree", routes),
+            &requests,
+            |b, requests| {
+                b.iter(|| {
+                    for req in requests {
+                        black_box(router.route(req).params);
+                    }
+                })
+            },
+        );
+
+        let mut recognizer = Recognizer::new();
+        for (i, pattern) in patterns.iter().enumerate() {
+            recognizer.add(pattern, i);
+        }
+        group.bench_with_input(
+            BenchmarkId::new("route_recognizer", routes),
+            &paths,
+            |b, paths| {
+                b.iter(|| {
+                    for path in paths {
+                        if let Ok(m) = recognizer.recognize(path) {
+                            let mut params = Params::new();
+                            params.clone_from(m.params());
+                            black_box((m.handler(), params));
+                        }
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_route);
+criterion_main!(benches);
@@ -3,7 +3,7 @@ use blacktea::{App, HttpResponse, Method, PathParams, Server, URLParams};
 async fn url_echo(params: URLParams) -> HttpResponse {
     let params = params.get("msg");
     if let Some(msg) = params {
-        HttpResponse::Ok().text(&msg)
+        HttpResponse::Ok().text(msg)
     } else {
         HttpResponse::Ok().text("Echo!")
     }
@@ -12,7 +12,7 @@ async fn url_echo(params: URLParams) -> HttpResponse {
 async fn path_echo(params: PathParams) -> HttpResponse {
     let params = params.find("msg");
     if let Some(msg) = params {
-        HttpResponse::Ok().text(&msg)
+        HttpResponse::Ok().text(msg)
     } else {
         HttpResponse::Ok().text("Echo!")
     }
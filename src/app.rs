@@ -14,26 +14,54 @@
 
 use crate::extract::FromRequest;
 use crate::factory::{factory, BoxServiceFactory, Handler, HandlerService};
+use crate::guard::Guard;
+use crate::layer::{box_layer, Error, HttpService, LayerMiddleware};
+use crate::middleware::Middleware;
+use crate::resource::{Action, Resource, ResourceService};
 use crate::response::HttpResponse;
-use hyper::Method;
+use crate::router::Constraint;
+use crate::timeout::Timeout;
+use hyper::header::HeaderValue;
+use hyper::{Body, Method, Request, Response};
+use std::any::type_name;
 use std::future::Future;
+use std::sync::Arc;
 
 pub struct App {
     apps: Vec<SubApp>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    timeout: Option<Timeout>,
 }
 
 pub struct SubApp {
     pub path: String,
+    /// Where the route was mounted; `path` already includes it.
+    pub prefix: String,
     pub method: Method,
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+    pub guards: Vec<Box<dyn Guard>>,
+    /// Param constraints parsed from the pattern when the route is registered.
+    pub constraints: Vec<(String, Constraint)>,
+    /// `path` without the constraints, e.g. `/v1/echo/:msg`.
+    pub(crate) pattern: String,
+    pub(crate) param_names: Vec<Arc<str>>,
+    pub(crate) middleware: Vec<Arc<dyn Middleware>>,
+    pub(crate) timeout: Option<Timeout>,
+    pub handler_name: &'static str,
     pub handler: BoxServiceFactory<HttpResponse>,
 }
 
 impl App {
     pub const fn new() -> Self {
-        Self { apps: Vec::new() }
+        Self {
+            apps: Vec::new(),
+            middleware: Vec::new(),
+            timeout: None,
+        }
     }
 
-    pub fn add<F, T, R>(&mut self, path: &str, method: Method, handler: F)
+    pub fn add<F, T, R>(&mut self, path: &str, method: Method, handler: F) -> &mut SubApp
     where
         F: Handler<T, R>,
         T: FromRequest,
@@ -42,10 +70,65 @@ impl App {
         let path = String::from(path);
         let subapp = SubApp::new(path, method, handler);
         self.apps.push(subapp);
+        self.apps.last_mut().unwrap()
+    }
+
+    /// Route the actions of `resource` under `path`, see `Resource`.
+    pub fn resource<R: Resource>(&mut self, path: &str, resource: R) {
+        let path = path.trim_end_matches('/');
+        let resource = Arc::new(resource);
+        for member in [false, true] {
+            let actions = Action::ALL.iter().filter(|a| a.member() == member);
+            let allow: Vec<String> = actions
+                .clone()
+                .filter(|a| a.implemented(&*resource))
+                .map(|a| a.method().to_string())
+                .collect();
+            if allow.is_empty() {
+                continue;
+            }
+            let allow = HeaderValue::from_str(&allow.join(", ")).unwrap();
+            let path = if member {
+                format!("{}/:id", path)
+            } else if path.is_empty() {
+                String::from("/")
+            } else {
+                String::from(path)
+            };
+            for &action in actions {
+                let service = ResourceService::new(resource.clone(), action, allow.clone());
+                let handler = factory(service);
+                let subapp =
+                    SubApp::from_factory(path.clone(), action.method(), type_name::<R>(), handler);
+                self.apps.push(subapp);
+            }
+        }
+    }
+
+    /// Run `middleware` around every route of the app, after server middleware
+    /// and before route middleware.
+    pub fn wrap<M: Middleware>(&mut self, middleware: M) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Cancel handlers of this app running longer than `timeout`, unless the
+    /// route sets its own.
+    pub fn timeout<T: Into<Timeout>>(&mut self, timeout: T) -> &mut Self {
+        self.timeout = Some(timeout.into());
+        self
     }
 
     pub fn apps(self) -> Vec<SubApp> {
-        self.apps
+        let mut apps = self.apps;
+        for subapp in &mut apps {
+            let route = std::mem::take(&mut subapp.middleware);
+            subapp.middleware = self.middleware.iter().cloned().chain(route).collect();
+            if subapp.timeout.is_none() {
+                subapp.timeout = self.timeout.clone();
+            }
+        }
+        apps
     }
 }
 
@@ -62,10 +145,72 @@ impl SubApp {
         T: FromRequest,
         R: Future<Output = HttpResponse> + Send + Sync + 'static,
     {
+        let handler = factory(HandlerService::new(handler));
+        Self::from_factory(path, method, type_name::<F>(), handler)
+    }
+
+    pub(crate) fn from_factory(
+        path: String,
+        method: Method,
+        handler_name: &'static str,
+        handler: BoxServiceFactory<HttpResponse>,
+    ) -> Self {
         Self {
             path,
+            prefix: String::new(),
             method,
-            handler: factory(HandlerService::new(handler)),
+            name: None,
+            tags: Vec::new(),
+            guards: Vec::new(),
+            constraints: Vec::new(),
+            pattern: String::new(),
+            param_names: Vec::new(),
+            middleware: Vec::new(),
+            timeout: None,
+            handler_name,
+            handler,
         }
     }
+
+    /// Name the route so that its URL can be built with `UrlFor::url_for`.
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = Some(String::from(name));
+        self
+    }
+
+    /// Attach a free-form tag, listed by `Server::routes`.
+    pub fn tag(&mut self, tag: &str) -> &mut Self {
+        self.tags.push(String::from(tag));
+        self
+    }
+
+    /// Only handle requests passing `guard`; guards are checked in the order added.
+    pub fn guard<G: Guard + 'static>(&mut self, guard: G) -> &mut Self {
+        self.guards.push(Box::new(guard));
+        self
+    }
+
+    /// Run `middleware` around this route's handler.
+    pub fn wrap<M: Middleware>(&mut self, middleware: M) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Cancel the handler when it runs longer than `timeout`.
+    pub fn timeout<T: Into<Timeout>>(&mut self, timeout: T) -> &mut Self {
+        self.timeout = Some(timeout.into());
+        self
+    }
+
+    /// Run this route's handler inside a tower layer, in order with `wrap`.
+    pub fn layer<L>(&mut self, layer: L) -> &mut Self
+    where
+        L: tower::Layer<HttpService> + Send + Sync + 'static,
+        L::Service:
+            tower::Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+        <L::Service as tower::Service<Request<Body>>>::Future: Send + 'static,
+        <L::Service as tower::Service<Request<Body>>>::Error: Into<Error>,
+    {
+        self.wrap(LayerMiddleware::new(box_layer(layer)))
+    }
 }
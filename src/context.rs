@@ -12,9 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::extract::RoutePattern;
+use crate::router::Params;
 use fnv::FnvHashMap;
 use hyper::{Body, Request};
-use route_recognizer::Params;
 use url::Url;
 
 #[derive(Debug)]
@@ -27,7 +28,7 @@ pub struct Context {
 impl Context {
     pub fn new(req: Request<Body>, path_params: Params) -> Self {
         let mut hashmap = FnvHashMap::default();
-        let url = format!("http://localhost{}", req.uri().to_string());
+        let url = format!("http://localhost{}", req.uri());
         let url = Url::parse(&url).unwrap();
         let params = url.query_pairs();
         for pair in params {
@@ -42,6 +43,14 @@ impl Context {
         }
     }
 
+    /// The pattern of the matched route, e.g. `/v1/echo/:msg`.
+    pub fn pattern(&self) -> Option<&str> {
+        self.req
+            .extensions()
+            .get::<RoutePattern>()
+            .map(|pattern| pattern.0.as_str())
+    }
+
     pub fn url_params(self, key: &str) -> Option<String> {
         let params = self.url_params.get(key);
         if let Some(params) = params {
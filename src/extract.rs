@@ -14,18 +14,42 @@
 
 use crate::context::Context;
 use crate::response::HttpResponse;
+use crate::router::UrlFor;
 use hyper::header::HeaderValue;
 // use hyper::http::Extensions;
 use hyper::{Error, HeaderMap, Version};
+use std::net::SocketAddr;
 
 pub type HyperResponse = hyper::Response<hyper::Body>;
 pub type Header = HeaderMap<HeaderValue>;
 pub type URL = hyper::Uri;
 pub type HttpVersion = Version;
 // pub type ProtocolExtensions = Extensions;
-pub type PathParams = route_recognizer::Params;
+pub type PathParams = crate::router::Params;
 pub type URLParams = fnv::FnvHashMap<String, String>;
 
+/// The pattern of the matched route, including the mount prefix.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoutePattern(pub String);
+
+/// Where the matched route's `App` was mounted, empty for routes added to the
+/// `Server` directly.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MountPrefix(pub String);
+
+/// The peer address of the connection, `None` when the router is embedded
+/// with `Server::into_service` and the outer stack doesn't provide it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RemoteAddr(pub Option<SocketAddr>);
+
+/// The id given to the request by `SetRequestId`, empty without it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+/// The request path with the mount prefix removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RelativePath(pub String);
+
 pub trait IntoResponse: Send + Sync {
     fn into_response(self) -> HyperResponse;
 }
@@ -96,6 +120,83 @@ impl FromRequest for URLParams {
     }
 }
 
+impl FromRequest for UrlFor {
+    type Error = Error;
+
+    fn from_request(c: &Context) -> Result<Self, Error> {
+        Ok(c.req
+            .extensions()
+            .get::<UrlFor>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+impl FromRequest for RoutePattern {
+    type Error = Error;
+
+    fn from_request(c: &Context) -> Result<Self, Error> {
+        Ok(c.req
+            .extensions()
+            .get::<RoutePattern>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+impl FromRequest for MountPrefix {
+    type Error = Error;
+
+    fn from_request(c: &Context) -> Result<Self, Error> {
+        Ok(c.req
+            .extensions()
+            .get::<MountPrefix>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+impl FromRequest for RemoteAddr {
+    type Error = Error;
+
+    fn from_request(c: &Context) -> Result<Self, Error> {
+        Ok(c.req
+            .extensions()
+            .get::<RemoteAddr>()
+            .copied()
+            .unwrap_or_default())
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = Error;
+
+    fn from_request(c: &Context) -> Result<Self, Error> {
+        Ok(c.req
+            .extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+impl FromRequest for RelativePath {
+    type Error = Error;
+
+    fn from_request(c: &Context) -> Result<Self, Error> {
+        let path = c.req.uri().path();
+        let prefix = c.req.extensions().get::<MountPrefix>();
+        let relative = prefix
+            .and_then(|prefix| path.strip_prefix(prefix.0.as_str()))
+            .unwrap_or(path);
+        if relative.starts_with('/') {
+            Ok(RelativePath(String::from(relative)))
+        } else {
+            Ok(RelativePath(format!("/{}", relative)))
+        }
+    }
+}
+
 impl FromRequest for () {
     type Error = Error;
 
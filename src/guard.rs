@@ -0,0 +1,111 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use hyper::header::{self, HeaderName};
+use hyper::{Body, Request};
+use mime::Mime;
+
+/// A predicate deciding whether a route may handle a request.
+///
+/// Guards attached to a route are checked in order and all of them must pass.
+/// If they don't, the router tries the next route registered with the same
+/// method and pattern.
+pub trait Guard: Send + Sync {
+    fn check(&self, req: &Request<Body>) -> bool;
+}
+
+impl<F> Guard for F
+where
+    F: Fn(&Request<Body>) -> bool + Send + Sync,
+{
+    fn check(&self, req: &Request<Body>) -> bool {
+        (self)(req)
+    }
+}
+
+/// Passes if the header `name` is present and equals `value`.
+pub fn header(name: &str, value: &str) -> impl Guard {
+    let name = HeaderName::from_bytes(name.as_bytes()).expect("Invalid header name.");
+    let value = String::from(value);
+    move |req: &Request<Body>| {
+        req.headers()
+            .get(&name)
+            .is_some_and(|v| v.as_bytes() == value.as_bytes())
+    }
+}
+
+/// Passes if the `Host` header equals `host`, ignoring case and port.
+pub fn host(host: &str) -> impl Guard {
+    let host = host.to_ascii_lowercase();
+    move |req: &Request<Body>| request_host(req).is_some_and(|h| h.eq_ignore_ascii_case(&host))
+}
+
+/// Passes if the `Content-Type` essence equals `content_type`, ignoring parameters.
+pub fn content_type(content_type: &str) -> impl Guard {
+    let expected: Mime = content_type.parse().expect("Invalid media type.");
+    move |req: &Request<Body>| {
+        req.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<Mime>().ok())
+            .is_some_and(|m| m.essence_str() == expected.essence_str())
+    }
+}
+
+/// Passes if the `Accept` header lists a media range covering `media_type`.
+///
+/// Ranges with `q=0` are ignored. A request without `Accept` passes.
+pub fn accept(media_type: &str) -> impl Guard {
+    let expected: Mime = media_type.parse().expect("Invalid media type.");
+    move |req: &Request<Body>| {
+        let accept = match req.headers().get(header::ACCEPT) {
+            Some(accept) => accept,
+            None => return true,
+        };
+        accept
+            .to_str()
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|range| range.trim().parse::<Mime>().ok())
+            .filter(|range| match range.get_param("q") {
+                Some(q) => q.as_str().parse::<f32>().map_or(true, |q| q > 0.0),
+                None => true,
+            })
+            .any(|range| match (range.type_(), range.subtype()) {
+                (mime::STAR, mime::STAR) => true,
+                (ty, mime::STAR) => ty == expected.type_(),
+                _ => range.essence_str() == expected.essence_str(),
+            })
+    }
+}
+
+/// Inverts another guard.
+pub fn not<G: Guard>(guard: G) -> impl Guard {
+    move |req: &Request<Body>| !guard.check(req)
+}
+
+/// The request host without port, from `Host` or the URI authority.
+pub(crate) fn request_host(req: &Request<Body>) -> Option<&str> {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| req.uri().host())?;
+    let end = if host.starts_with('[') {
+        host.find(']').map_or(host.len(), |i| i + 1)
+    } else {
+        host.find(':').unwrap_or(host.len())
+    };
+    Some(&host[..end])
+}
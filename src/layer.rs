@@ -0,0 +1,87 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::context::Context;
+use crate::factory::BoxFuture;
+use crate::middleware::request_id::request_id;
+use crate::middleware::{Middleware, Next};
+use crate::response::HttpResponse;
+use crate::router::Params;
+use hyper::{Body, Request, Response};
+use std::sync::Mutex;
+use sync_wrapper::SyncFuture;
+use tower::util::BoxCloneService;
+use tower::{service_fn, Layer, Service, ServiceExt};
+
+pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// The router, or a route's handler, as a boxed tower service.
+pub type HttpService = BoxCloneService<Request<Body>, Response<Body>, Error>;
+
+pub(crate) type BoxLayer = Box<dyn Fn(HttpService) -> HttpService + Send + Sync>;
+
+pub(crate) fn box_layer<L>(layer: L) -> BoxLayer
+where
+    L: Layer<HttpService> + Send + Sync + 'static,
+    L::Service: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    <L::Service as Service<Request<Body>>>::Error: Into<Error>,
+{
+    Box::new(move |service| BoxCloneService::new(layer.layer(service).map_err(Into::into)))
+}
+
+/// The rest of the middleware chain, carried through a route's layers in the
+/// request extensions.
+struct Pending(Next, Params);
+
+/// Runs the rest of a route's chain inside a tower layer stack.
+///
+/// The stack is built once, so stateful layers such as concurrency limits are
+/// shared by all requests to the route.
+pub(crate) struct LayerMiddleware {
+    service: Mutex<HttpService>,
+}
+
+impl LayerMiddleware {
+    pub(crate) fn new(layer: BoxLayer) -> Self {
+        let inner = service_fn(|mut req: Request<Body>| async move {
+            let Pending(next, params) = req
+                .extensions_mut()
+                .remove::<Pending>()
+                .ok_or("Route layer dropped the request extensions.")?;
+            Ok::<_, Error>(next.run(Context::new(req, params)).await.res())
+        });
+        Self {
+            service: Mutex::new(layer(BoxCloneService::new(inner))),
+        }
+    }
+}
+
+impl Middleware for LayerMiddleware {
+    fn call(&self, c: Context, next: Next) -> BoxFuture<HttpResponse> {
+        let service = self.service.lock().unwrap().clone();
+        let mut req = c.req;
+        let id = String::from(request_id(&req));
+        req.extensions_mut().insert(Pending(next, c.path_params));
+        Box::pin(SyncFuture::new(async move {
+            match service.oneshot(req).await {
+                Ok(res) => HttpResponse::from_builder(res),
+                Err(e) => {
+                    error!("Route layer failed on request {}: {}", id, e);
+                    HttpResponse::InternalServerError().empty()
+                }
+            }
+        }))
+    }
+}
@@ -17,17 +17,35 @@
 extern crate log;
 mod app;
 pub mod context;
+pub mod guard;
+pub mod middleware;
+mod resource;
 mod response;
 pub mod router;
 mod server;
+mod timeout;
+mod tree;
+mod version;
 
 mod extract;
 pub mod factory;
+pub mod layer;
+mod mount;
 
 pub use self::app::App;
 pub use self::context::Context;
 pub use self::extract::HyperResponse;
+pub use self::guard::Guard;
+pub use self::middleware::{Middleware, Next};
+pub use self::mount::ServiceMount;
+pub use self::resource::Resource;
 pub use self::response::HttpResponse;
-pub use self::server::{Executor, Server};
-pub use extract::{Header, HttpVersion, PathParams, URLParams, URL};
+pub use self::router::{TrailingSlash, UrlFor};
+pub use self::server::{Executor, RouterHandle, Server};
+pub use self::timeout::Timeout;
+pub use self::version::{Version, Versioned};
+pub use extract::{
+    Header, HttpVersion, MountPrefix, PathParams, RelativePath, RemoteAddr, RequestId,
+    RoutePattern, URLParams, URL,
+};
 pub use hyper::Method;
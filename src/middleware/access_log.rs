@@ -0,0 +1,412 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! One log line per request, written once the response has been produced.
+
+use crate::context::Context;
+use crate::extract::{RemoteAddr, RequestId};
+use crate::factory::BoxFuture;
+use crate::middleware::{Middleware, Next};
+use crate::response::HttpResponse;
+use futures_core::Stream;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{self, HeaderName};
+use hyper::{Body, StatusCode};
+use serde_json::Value;
+use std::fmt::Write;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Logs every request with its status, size and latency.
+///
+/// Responses with a known length are logged when the handler returns,
+/// streamed responses once the last chunk has been sent or the client went
+/// away. Add it with `Server::wrap` to log every request.
+#[derive(Clone)]
+pub struct AccessLog {
+    layout: Arc<Layout>,
+    sink: Arc<dyn LogSink>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `127.0.0.1 - - [10/Oct/2021:13:55:36 +0000] "GET /a HTTP/1.1" 200 2326`
+    Common,
+    /// Common Log Format followed by the quoted `Referer` and `User-Agent`.
+    Combined,
+    /// One JSON object per line with the configured fields.
+    Json,
+}
+
+/// A value in a JSON log line, keyed by the name in parentheses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// `remote_addr`
+    RemoteAddr,
+    /// `time`, RFC 3339 in UTC.
+    Time,
+    /// `method`
+    Method,
+    /// `uri`
+    Uri,
+    /// `protocol`
+    Protocol,
+    /// `pattern`, the matched route.
+    Pattern,
+    /// `request_id`, see `SetRequestId`.
+    RequestId,
+    /// `status`
+    Status,
+    /// `bytes`, the size of the response body.
+    Bytes,
+    /// `duration_ms`
+    Duration,
+    /// `referer`
+    Referer,
+    /// `user_agent`
+    UserAgent,
+    /// A request header, keyed by its lowercase name.
+    Header(HeaderName),
+}
+
+/// Where log lines go.
+pub trait LogSink: Send + Sync + 'static {
+    fn write(&self, line: &str);
+}
+
+impl<F> LogSink for F
+where
+    F: Fn(&str) + Send + Sync + 'static,
+{
+    fn write(&self, line: &str) {
+        (self)(line)
+    }
+}
+
+/// Writes lines with `info!` to the `blacktea::access` target.
+struct Logger;
+
+impl LogSink for Logger {
+    fn write(&self, line: &str) {
+        info!(target: "blacktea::access", "{}", line);
+    }
+}
+
+#[derive(Clone)]
+struct Layout {
+    format: Format,
+    fields: Vec<Field>,
+}
+
+/// What is known about a request before its response.
+struct Entry {
+    layout: Arc<Layout>,
+    sink: Arc<dyn LogSink>,
+    remote: RemoteAddr,
+    time: SystemTime,
+    start: Instant,
+    method: String,
+    uri: String,
+    protocol: &'static str,
+    pattern: Option<String>,
+    request_id: Option<String>,
+    headers: Vec<(HeaderName, Option<String>)>,
+    status: StatusCode,
+}
+
+/// A streamed response body that logs its entry when dropped.
+struct LoggedBody {
+    inner: Body,
+    bytes: u64,
+    entry: Entry,
+}
+
+impl AccessLog {
+    pub fn new() -> Self {
+        Self {
+            layout: Arc::new(Layout {
+                format: Format::Combined,
+                fields: vec![
+                    Field::Time,
+                    Field::RemoteAddr,
+                    Field::Method,
+                    Field::Uri,
+                    Field::Protocol,
+                    Field::Pattern,
+                    Field::RequestId,
+                    Field::Status,
+                    Field::Bytes,
+                    Field::Duration,
+                    Field::Referer,
+                    Field::UserAgent,
+                ],
+            }),
+            sink: Arc::new(Logger),
+        }
+    }
+
+    /// `Format::Combined` by default.
+    pub fn format(&mut self, format: Format) -> &mut Self {
+        Arc::make_mut(&mut self.layout).format = format;
+        self
+    }
+
+    /// Replace the fields of JSON lines. The text formats have fixed fields.
+    pub fn fields(&mut self, fields: &[Field]) -> &mut Self {
+        Arc::make_mut(&mut self.layout).fields = fields.to_vec();
+        self
+    }
+
+    /// Send lines to `sink` instead of the `log` crate.
+    pub fn sink<S: LogSink>(&mut self, sink: S) -> &mut Self {
+        self.sink = Arc::new(sink);
+        self
+    }
+
+    fn entry(&self, c: &Context) -> Entry {
+        let req = &c.req;
+        let mut wanted = vec![header::REFERER, header::USER_AGENT];
+        for field in &self.layout.fields {
+            if let Field::Header(name) = field {
+                wanted.push(name.clone());
+            }
+        }
+        let headers = wanted
+            .into_iter()
+            .map(|name| {
+                let value = req
+                    .headers()
+                    .get(&name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(String::from);
+                (name, value)
+            })
+            .collect();
+        Entry {
+            layout: self.layout.clone(),
+            sink: self.sink.clone(),
+            remote: req
+                .extensions()
+                .get::<RemoteAddr>()
+                .copied()
+                .unwrap_or_default(),
+            time: SystemTime::now(),
+            start: Instant::now(),
+            method: req.method().to_string(),
+            uri: req.uri().to_string(),
+            protocol: protocol(req.version()),
+            pattern: c.pattern().map(String::from),
+            request_id: req.extensions().get::<RequestId>().map(|id| id.0.clone()),
+            headers,
+            status: StatusCode::OK,
+        }
+    }
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for AccessLog {
+    fn call(&self, c: Context, next: Next) -> BoxFuture<HttpResponse> {
+        let mut entry = self.entry(&c);
+        Box::pin(async move {
+            let (parts, body) = next.run(c).await.res().into_parts();
+            entry.status = parts.status;
+            let body = match HttpBody::size_hint(&body).exact() {
+                Some(bytes) => {
+                    entry.write(bytes);
+                    body
+                }
+                None => Body::wrap_stream(LoggedBody {
+                    inner: body,
+                    bytes: 0,
+                    entry,
+                }),
+            };
+            HttpResponse::from_builder(hyper::Response::from_parts(parts, body))
+        })
+    }
+}
+
+impl Entry {
+    fn write(&self, bytes: u64) {
+        let line = match self.layout.format {
+            Format::Common => self.common(bytes),
+            Format::Combined => {
+                let mut line = self.common(bytes);
+                let _ = write!(
+                    line,
+                    " \"{}\" \"{}\"",
+                    quoted(self.header(&header::REFERER).unwrap_or("-")),
+                    quoted(self.header(&header::USER_AGENT).unwrap_or("-"))
+                );
+                line
+            }
+            Format::Json => self.json(bytes),
+        };
+        self.sink.write(&line);
+    }
+
+    fn common(&self, bytes: u64) -> String {
+        let remote = match self.remote.0 {
+            Some(addr) => addr.ip().to_string(),
+            None => String::from("-"),
+        };
+        let bytes = match bytes {
+            0 => String::from("-"),
+            bytes => bytes.to_string(),
+        };
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            remote,
+            clf_time(self.time),
+            self.method,
+            quoted(&self.uri),
+            self.protocol,
+            self.status.as_u16(),
+            bytes
+        )
+    }
+
+    fn json(&self, bytes: u64) -> String {
+        let mut line = String::from("{");
+        for field in &self.layout.fields {
+            let (key, value): (&str, Value) = match field {
+                Field::RemoteAddr => (
+                    "remote_addr",
+                    self.remote.0.map(|addr| addr.to_string()).into(),
+                ),
+                Field::Time => ("time", rfc3339(self.time).into()),
+                Field::Method => ("method", self.method.clone().into()),
+                Field::Uri => ("uri", self.uri.clone().into()),
+                Field::Protocol => ("protocol", self.protocol.into()),
+                Field::Pattern => ("pattern", self.pattern.clone().into()),
+                Field::RequestId => ("request_id", self.request_id.clone().into()),
+                Field::Status => ("status", self.status.as_u16().into()),
+                Field::Bytes => ("bytes", bytes.into()),
+                Field::Duration => ("duration_ms", millis(self.start.elapsed()).into()),
+                Field::Referer => ("referer", self.header(&header::REFERER).into()),
+                Field::UserAgent => ("user_agent", self.header(&header::USER_AGENT).into()),
+                Field::Header(name) => (name.as_str(), self.header(name).into()),
+            };
+            if line.len() > 1 {
+                line.push(',');
+            }
+            let _ = write!(line, "{}:{}", Value::from(key), value);
+        }
+        line.push('}');
+        line
+    }
+
+    fn header(&self, name: &HeaderName) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, v)| v.as_deref())
+    }
+}
+
+impl Stream for LoggedBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.bytes += chunk.len() as u64;
+        }
+        poll
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        self.entry.write(self.bytes);
+    }
+}
+
+fn protocol(version: hyper::Version) -> &'static str {
+    match version {
+        hyper::Version::HTTP_09 => "HTTP/0.9",
+        hyper::Version::HTTP_10 => "HTTP/1.0",
+        hyper::Version::HTTP_2 => "HTTP/2.0",
+        hyper::Version::HTTP_3 => "HTTP/3.0",
+        _ => "HTTP/1.1",
+    }
+}
+
+fn quoted(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn millis(d: Duration) -> f64 {
+    (d.as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// `10/Oct/2021:13:55:36 +0000`
+fn clf_time(time: SystemTime) -> String {
+    let (year, month, day, h, m, s) = civil(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        h,
+        m,
+        s
+    )
+}
+
+/// `2021-10-10T13:55:36Z`
+fn rfc3339(time: SystemTime) -> String {
+    let (year, month, day, h, m, s) = civil(time);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, h, m, s
+    )
+}
+
+/// The UTC calendar date and time of `time`.
+fn civil(time: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Howard Hinnant's days_from_civil, inverted.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        year,
+        month,
+        day,
+        (rem / 3600) as u32,
+        (rem % 3600 / 60) as u32,
+        (rem % 60) as u32,
+    )
+}
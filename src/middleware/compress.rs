@@ -0,0 +1,383 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Response compression.
+
+use crate::context::Context;
+use crate::factory::BoxFuture;
+use crate::middleware::{Middleware, Next};
+use crate::response::HttpResponse;
+use async_compression::tokio::write::{BrotliEncoder, GzipEncoder, ZlibEncoder};
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Method, Response, StatusCode};
+use mime::Mime;
+use std::io;
+use std::mem;
+use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Compresses response bodies with the best encoding the client accepts.
+///
+/// Bodies of a known size below the minimum are sent as they are; streamed
+/// bodies are compressed as they are produced, and each chunk is flushed to
+/// the client as soon as it is compressed. Responses that are already
+/// encoded, partial, or marked `Cache-Control: no-transform` are left alone.
+#[derive(Clone)]
+pub struct Compress {
+    config: Arc<Config>,
+}
+
+/// Encodings in order of preference when the client rates them equally.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    /// HTTP `deflate`, which is zlib-wrapped deflate (RFC 9110).
+    Deflate,
+}
+
+#[derive(Clone)]
+struct Config {
+    encodings: Vec<Encoding>,
+    min_size: u64,
+    content_types: Vec<Mime>,
+}
+
+impl Compress {
+    /// Brotli, gzip and deflate for text (except event streams), JSON,
+    /// JavaScript, XML and SVG bodies of at least 1 KiB.
+    pub fn new() -> Self {
+        let content_types = [
+            "text/*",
+            "application/json",
+            "application/javascript",
+            "application/xml",
+            "image/svg+xml",
+        ];
+        Self {
+            config: Arc::new(Config {
+                encodings: vec![Encoding::Brotli, Encoding::Gzip, Encoding::Deflate],
+                min_size: 1024,
+                content_types: parse_types(&content_types),
+            }),
+        }
+    }
+
+    /// Offer only `encodings`, preferred in the order given.
+    pub fn encodings(&mut self, encodings: &[Encoding]) -> &mut Self {
+        Arc::make_mut(&mut self.config).encodings = encodings.to_vec();
+        self
+    }
+
+    /// Leave bodies smaller than `bytes` uncompressed.
+    pub fn min_size(&mut self, bytes: u64) -> &mut Self {
+        Arc::make_mut(&mut self.config).min_size = bytes;
+        self
+    }
+
+    /// Compress only these media types; `text/*` covers a whole type, and
+    /// `+json`/`+xml` suffixes match their base type. `text/event-stream`
+    /// is only compressed when listed by name.
+    pub fn content_types(&mut self, types: &[&str]) -> &mut Self {
+        Arc::make_mut(&mut self.config).content_types = parse_types(types);
+        self
+    }
+}
+
+impl Default for Compress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Compress {
+    fn call(&self, c: Context, next: Next) -> BoxFuture<HttpResponse> {
+        let config = self.config.clone();
+        let encoding = match c.req.method() {
+            &Method::HEAD => None,
+            _ => config.negotiate(c.req.headers()),
+        };
+        Box::pin(async move {
+            let res = next.run(c).await.res();
+            HttpResponse::from_builder(config.compress(res, encoding))
+        })
+    }
+}
+
+impl Config {
+    /// The preferred encoding with the highest q-value in `Accept-Encoding`.
+    /// A coding listed by name takes its own q-value over that of `*`.
+    fn negotiate(&self, headers: &HeaderMap) -> Option<Encoding> {
+        let mut named: Vec<(Encoding, f32)> = Vec::new();
+        let mut any = None;
+        for item in headers
+            .get_all(header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+        {
+            let mut parts = item.split(';');
+            let coding = parts.next().unwrap_or_default().trim();
+            let q = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if coding == "*" {
+                any = Some(q);
+            }
+            for &encoding in &self.encodings {
+                if coding.eq_ignore_ascii_case(encoding.as_str())
+                    || (encoding == Encoding::Gzip && coding.eq_ignore_ascii_case("x-gzip"))
+                {
+                    named.push((encoding, q));
+                }
+            }
+        }
+        self.encodings
+            .iter()
+            .filter_map(|&encoding| {
+                let q = named
+                    .iter()
+                    .find(|(e, _)| *e == encoding)
+                    .map(|&(_, q)| q)
+                    .or(any)?;
+                Some((encoding, q))
+            })
+            .filter(|&(_, q)| q > 0.0)
+            // `max_by` keeps the last of equal elements, so go backwards to
+            // prefer earlier encodings.
+            .rev()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(encoding, _)| encoding)
+    }
+
+    fn compressible(&self, res: &Response<Body>) -> bool {
+        let status = res.status();
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            || status == StatusCode::PARTIAL_CONTENT
+        {
+            return false;
+        }
+        let headers = res.headers();
+        if headers.contains_key(header::CONTENT_ENCODING)
+            || headers.contains_key(header::CONTENT_RANGE)
+        {
+            return false;
+        }
+        let no_transform = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|d| d.trim().eq_ignore_ascii_case("no-transform"));
+        if no_transform {
+            return false;
+        }
+        let mime = match headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<Mime>().ok())
+        {
+            Some(mime) => mime,
+            None => return false,
+        };
+        let event_stream = mime.essence_str() == mime::TEXT_EVENT_STREAM.essence_str();
+        self.content_types.iter().any(|allowed| {
+            allowed.type_() == mime.type_()
+                && ((allowed.subtype() == mime::STAR && !event_stream)
+                    || allowed.subtype() == mime.subtype()
+                    || mime.suffix().is_some_and(|s| s == allowed.subtype()))
+        })
+    }
+
+    fn compress(&self, res: Response<Body>, encoding: Option<Encoding>) -> Response<Body> {
+        if !self.compressible(&res) {
+            return res;
+        }
+        let (mut parts, body) = res.into_parts();
+        parts
+            .headers
+            .append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None => return Response::from_parts(parts, body),
+        };
+        let size = HttpBody::size_hint(&body).exact();
+        if size.is_some_and(|size| size < self.min_size) {
+            return Response::from_parts(parts, body);
+        }
+
+        let headers = &mut parts.headers;
+        headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+        headers.remove(header::CONTENT_LENGTH);
+        headers.remove(header::ACCEPT_RANGES);
+        // The compressed body is a different representation.
+        if let Some(etag) = headers.get(header::ETAG) {
+            if !etag.as_bytes().starts_with(b"W/") {
+                let mut weak = b"W/".to_vec();
+                weak.extend_from_slice(etag.as_bytes());
+                if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+                    headers.insert(header::ETAG, weak);
+                }
+            }
+        }
+
+        let body = match encoding {
+            Encoding::Brotli => encode(body, BrotliEncoder::new(Vec::new())),
+            Encoding::Gzip => encode(body, GzipEncoder::new(Vec::new())),
+            Encoding::Deflate => encode(body, ZlibEncoder::new(Vec::new())),
+        };
+        Response::from_parts(parts, body)
+    }
+}
+
+/// An encoder writing into a buffer that is drained after every chunk.
+trait Encoder: AsyncWrite + Unpin + Send + 'static {
+    fn output(&mut self) -> &mut Vec<u8>;
+}
+
+impl Encoder for BrotliEncoder<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+}
+
+impl Encoder for GzipEncoder<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+}
+
+impl Encoder for ZlibEncoder<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+}
+
+/// Compress `body` chunk by chunk, flushing the encoder after each one so
+/// that streamed responses such as long polls aren't held back.
+fn encode<E: Encoder>(body: Body, encoder: E) -> Body {
+    let chunks =
+        futures_util::stream::try_unfold((body, Some(encoder)), |(mut body, encoder)| async move {
+            let mut encoder = match encoder {
+                Some(encoder) => encoder,
+                None => return Ok(None),
+            };
+            loop {
+                match body.data().await {
+                    Some(chunk) => {
+                        let chunk = chunk.map_err(io::Error::other)?;
+                        encoder.write_all(&chunk).await?;
+                        encoder.flush().await?;
+                    }
+                    None => {
+                        encoder.shutdown().await?;
+                        let output = Bytes::from(mem::take(encoder.output()));
+                        return Ok::<_, io::Error>(Some((output, (body, None))));
+                    }
+                }
+                let output = mem::take(encoder.output());
+                if !output.is_empty() {
+                    return Ok(Some((Bytes::from(output), (body, Some(encoder)))));
+                }
+            }
+        });
+    Body::wrap_stream(chunks)
+}
+
+impl Encoding {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+fn parse_types(types: &[&str]) -> Vec<Mime> {
+    types
+        .iter()
+        .map(|t| t.parse().expect("Invalid content type."))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compress, Encoding};
+    use hyper::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING};
+
+    fn negotiate(compress: &Compress, accept: &[&'static str]) -> Option<Encoding> {
+        let mut headers = HeaderMap::new();
+        for value in accept {
+            headers.append(ACCEPT_ENCODING, HeaderValue::from_static(value));
+        }
+        compress.config.negotiate(&headers)
+    }
+
+    #[test]
+    fn absent_header_means_no_encoding() {
+        let compress = Compress::new();
+        assert_eq!(negotiate(&compress, &[]), None);
+        assert_eq!(negotiate(&compress, &["identity"]), None);
+    }
+
+    #[test]
+    fn ties_go_to_the_preferred_encoding() {
+        let mut compress = Compress::new();
+        assert_eq!(negotiate(&compress, &["gzip, br"]), Some(Encoding::Brotli));
+        assert_eq!(
+            negotiate(&compress, &["deflate", "gzip"]),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate(&compress, &["*"]), Some(Encoding::Brotli));
+        compress.encodings(&[Encoding::Gzip, Encoding::Brotli]);
+        assert_eq!(negotiate(&compress, &["br, gzip"]), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn higher_q_wins() {
+        let compress = Compress::new();
+        let accept = ["gzip;q=1.0, br;q=0.5"];
+        assert_eq!(negotiate(&compress, &accept), Some(Encoding::Gzip));
+        let accept = ["br;q=0.2, *;q=0.8"];
+        assert_eq!(negotiate(&compress, &accept), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn zero_q_overrides_wildcard() {
+        let compress = Compress::new();
+        assert_eq!(negotiate(&compress, &["br;q=0, *"]), Some(Encoding::Gzip));
+        let accept = ["br;q=0, gzip;q=0, *"];
+        assert_eq!(negotiate(&compress, &accept), Some(Encoding::Deflate));
+        assert_eq!(negotiate(&compress, &["*;q=0"]), None);
+        assert_eq!(negotiate(&compress, &["gzip;q=0"]), None);
+    }
+
+    #[test]
+    fn x_gzip_is_gzip() {
+        let compress = Compress::new();
+        assert_eq!(negotiate(&compress, &["x-gzip"]), Some(Encoding::Gzip));
+        assert_eq!(
+            negotiate(&compress, &["X-GZIP;q=0.5, deflate;q=0.4"]),
+            Some(Encoding::Gzip)
+        );
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-flight request limits.
+
+use crate::context::Context;
+use crate::factory::BoxFuture;
+use crate::middleware::{Middleware, Next};
+use crate::response::HttpResponse;
+use futures_core::Stream;
+use hyper::body::{Bytes, HttpBody};
+use hyper::Body;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+use sync_wrapper::SyncFuture;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Runs at most a fixed number of requests at once.
+///
+/// Further requests wait for a free slot for a short while, by default
+/// 100 ms with at most as many waiting as running, and are then answered
+/// with `503 Service Unavailable` and `Retry-After`. A request holds its
+/// slot until a streamed response body has been sent. Wrap it on the `Server`
+/// for a global limit or on a route for that route alone; clones share one
+/// limit.
+#[derive(Clone)]
+pub struct ConcurrencyLimit {
+    max: usize,
+    slots: Arc<Semaphore>,
+    waiting: Arc<AtomicUsize>,
+    max_waiting: usize,
+    wait: Duration,
+    retry_after: u64,
+}
+
+/// Counts a request as waiting while it lives.
+struct Waiting(Arc<AtomicUsize>);
+
+/// A streamed response body that frees its slot when dropped.
+struct PermitBody {
+    inner: Body,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max: usize) -> Self {
+        assert!(max > 0, "Concurrency limit must be positive.");
+        Self {
+            max,
+            slots: Arc::new(Semaphore::new(max)),
+            waiting: Arc::new(AtomicUsize::new(0)),
+            max_waiting: max,
+            wait: Duration::from_millis(100),
+            retry_after: 1,
+        }
+    }
+
+    /// How many requests may wait for a slot; more are shed at once.
+    pub fn max_waiting(&mut self, requests: usize) -> &mut Self {
+        self.max_waiting = requests;
+        self
+    }
+
+    /// How long a request may wait for a slot.
+    pub fn wait(&mut self, wait: Duration) -> &mut Self {
+        self.wait = wait;
+        self
+    }
+
+    /// The `Retry-After` sent with shed requests, in seconds.
+    pub fn retry_after(&mut self, seconds: u64) -> &mut Self {
+        self.retry_after = seconds;
+        self
+    }
+
+    fn shed(&self, c: &Context) -> HttpResponse {
+        warn!(
+            "Shedding {} {}: {} requests in flight",
+            c.req.method(),
+            c.req.uri(),
+            self.max
+        );
+        HttpResponse::ServiceUnavailable()
+            .header("Retry-After", &self.retry_after.to_string())
+            .empty()
+    }
+}
+
+impl Middleware for ConcurrencyLimit {
+    fn call(&self, c: Context, next: Next) -> BoxFuture<HttpResponse> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Box::pin(async move { hold(permit, next.run(c).await) });
+        }
+        if self.waiting.fetch_add(1, Ordering::SeqCst) >= self.max_waiting {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            let res = self.shed(&c);
+            return Box::pin(async move { res });
+        }
+        let waiting = Waiting(self.waiting.clone());
+        let limit = self.clone();
+        Box::pin(SyncFuture::new(async move {
+            let acquire = limit.slots.clone().acquire_owned();
+            let permit = tokio::time::timeout(limit.wait, acquire).await;
+            drop(waiting);
+            match permit {
+                Ok(Ok(permit)) => hold(permit, next.run(c).await),
+                _ => limit.shed(&c),
+            }
+        }))
+    }
+}
+
+/// Keep `permit` until the body of `res` has been sent.
+fn hold(permit: OwnedSemaphorePermit, res: HttpResponse) -> HttpResponse {
+    let (parts, body) = res.res().into_parts();
+    if HttpBody::size_hint(&body).exact().is_some() {
+        return HttpResponse::from_builder(hyper::Response::from_parts(parts, body));
+    }
+    let body = Body::wrap_stream(PermitBody {
+        inner: body,
+        _permit: permit,
+    });
+    HttpResponse::from_builder(hyper::Response::from_parts(parts, body))
+}
+
+impl Stream for PermitBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,264 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cross-Origin Resource Sharing.
+
+use crate::context::Context;
+use crate::factory::BoxFuture;
+use crate::middleware::{Middleware, Next};
+use crate::response::HttpResponse;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::{Method, StatusCode};
+use regex::Regex;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Answers CORS preflight requests and adds CORS headers to responses.
+///
+/// Add it with `Server::wrap`: preflights are `OPTIONS` requests that usually
+/// have no route, so app or route middleware never sees them.
+#[derive(Clone)]
+pub struct Cors {
+    config: Arc<Config>,
+}
+
+#[derive(Clone)]
+struct Config {
+    any_origin: bool,
+    origins: Vec<String>,
+    patterns: Vec<Regex>,
+    methods: Vec<Method>,
+    any_header: bool,
+    headers: Vec<HeaderName>,
+    expose: Vec<HeaderName>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// Allows no origin until `allow_origin` is called. The allowed methods
+    /// default to `GET`, `HEAD`, `POST`, `PUT`, `PATCH` and `DELETE`.
+    pub fn new() -> Self {
+        Self {
+            config: Arc::new(Config {
+                any_origin: false,
+                origins: Vec::new(),
+                patterns: Vec::new(),
+                methods: vec![
+                    Method::GET,
+                    Method::HEAD,
+                    Method::POST,
+                    Method::PUT,
+                    Method::PATCH,
+                    Method::DELETE,
+                ],
+                any_header: false,
+                headers: Vec::new(),
+                expose: Vec::new(),
+                credentials: false,
+                max_age: None,
+            }),
+        }
+    }
+
+    /// Allow `origin`, e.g. `https://example.com`. `*` alone allows any
+    /// origin, and elsewhere matches any characters other than `/`, as in
+    /// `https://*.example.com`.
+    pub fn allow_origin(&mut self, origin: &str) -> &mut Self {
+        let config = Arc::make_mut(&mut self.config);
+        if origin == "*" {
+            config.any_origin = true;
+        } else if origin.contains('*') {
+            let pattern = origin
+                .split('*')
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join("[^/]+");
+            let pattern = format!("^{}$", pattern);
+            config
+                .patterns
+                .push(Regex::new(&pattern).expect("Invalid origin pattern."));
+        } else {
+            config
+                .origins
+                .push(String::from(origin.trim_end_matches('/')));
+        }
+        self
+    }
+
+    pub fn allow_methods(&mut self, methods: &[Method]) -> &mut Self {
+        Arc::make_mut(&mut self.config).methods = methods.to_vec();
+        self
+    }
+
+    /// Request headers allowed besides the CORS-safelisted ones.
+    pub fn allow_headers(&mut self, headers: &[&str]) -> &mut Self {
+        Arc::make_mut(&mut self.config).headers = header_names(headers);
+        self
+    }
+
+    /// Allow whatever request headers a preflight asks for.
+    pub fn allow_any_header(&mut self) -> &mut Self {
+        Arc::make_mut(&mut self.config).any_header = true;
+        self
+    }
+
+    /// Response headers scripts may read besides the CORS-safelisted ones.
+    pub fn expose_headers(&mut self, headers: &[&str]) -> &mut Self {
+        Arc::make_mut(&mut self.config).expose = header_names(headers);
+        self
+    }
+
+    /// Allow cookies and authorization. The origin is then always echoed,
+    /// since browsers reject `*` for credentialed requests.
+    pub fn allow_credentials(&mut self) -> &mut Self {
+        Arc::make_mut(&mut self.config).credentials = true;
+        self
+    }
+
+    /// How long browsers may cache a preflight answer.
+    pub fn max_age(&mut self, max_age: Duration) -> &mut Self {
+        Arc::make_mut(&mut self.config).max_age = Some(max_age);
+        self
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn call(&self, c: Context, next: Next) -> BoxFuture<HttpResponse> {
+        let config = self.config.clone();
+        let origin = c.req.headers().get(header::ORIGIN).cloned();
+        let preflight = c.req.method() == Method::OPTIONS
+            && c.req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if let (Some(origin), true) = (&origin, preflight) {
+            let res = config.preflight(origin, c.req.headers());
+            return Box::pin(async move { res });
+        }
+        Box::pin(async move {
+            let mut res = next.run(c).await.res();
+            let headers = res.headers_mut();
+            if let Some(allow_origin) = origin.and_then(|o| config.allow_origin(&o)) {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+                if config.credentials {
+                    headers.insert(
+                        header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                        HeaderValue::from_static("true"),
+                    );
+                }
+                if !config.expose.is_empty() {
+                    headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, join(&config.expose));
+                }
+            }
+            // The response differs by origin unless it's always `*`.
+            if !config.any_origin || config.credentials {
+                headers.append(header::VARY, HeaderValue::from_static("Origin"));
+            }
+            HttpResponse::from_builder(res)
+        })
+    }
+}
+
+impl Config {
+    /// The `Access-Control-Allow-Origin` value for `origin`, if it's allowed.
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        if self.any_origin && !self.credentials {
+            return Some(HeaderValue::from_static("*"));
+        }
+        let allowed = self.any_origin || {
+            let origin = origin.to_str().ok()?;
+            self.origins.iter().any(|o| o == origin)
+                || self.patterns.iter().any(|p| p.is_match(origin))
+        };
+        allowed.then(|| origin.clone())
+    }
+
+    fn preflight(&self, origin: &HeaderValue, req: &HeaderMap) -> HttpResponse {
+        let allow_origin = self.allow_origin(origin);
+        let method = req
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|m| Method::from_bytes(m.as_bytes()).ok());
+        let requested: Vec<&str> = req
+            .get_all(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|h| !h.is_empty())
+            .collect();
+        let method_allowed = method.is_some_and(|m| self.methods.contains(&m));
+        let headers_allowed = self.any_header
+            || requested.iter().all(|h| {
+                self.headers
+                    .iter()
+                    .any(|a| a.as_str().eq_ignore_ascii_case(h))
+            });
+
+        let mut res = hyper::Response::builder()
+            .header(header::VARY, "Origin")
+            .header(header::VARY, "Access-Control-Request-Method")
+            .header(header::VARY, "Access-Control-Request-Headers");
+        let allow_origin = match allow_origin {
+            Some(allow_origin) if method_allowed && headers_allowed => allow_origin,
+            _ => {
+                let res = res.status(StatusCode::FORBIDDEN).body(hyper::Body::empty());
+                return HttpResponse::from_builder(res.unwrap());
+            }
+        };
+        res = res
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, self.methods_value());
+        if !requested.is_empty() {
+            let allow_headers = if self.any_header {
+                HeaderValue::from_str(&requested.join(", ")).ok()
+            } else {
+                Some(join(&self.headers))
+            };
+            if let Some(allow_headers) = allow_headers {
+                res = res.header(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+            }
+        }
+        if self.credentials {
+            res = res.header(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+        }
+        if let Some(max_age) = self.max_age {
+            res = res.header(header::ACCESS_CONTROL_MAX_AGE, max_age.as_secs());
+        }
+        HttpResponse::from_builder(res.body(hyper::Body::empty()).unwrap())
+    }
+
+    fn methods_value(&self) -> HeaderValue {
+        let methods: Vec<&str> = self.methods.iter().map(Method::as_str).collect();
+        HeaderValue::from_str(&methods.join(", ")).unwrap()
+    }
+}
+
+fn header_names(headers: &[&str]) -> Vec<HeaderName> {
+    headers
+        .iter()
+        .map(|h| HeaderName::from_bytes(h.as_bytes()).expect("Invalid header name."))
+        .collect()
+}
+
+fn join(headers: &[HeaderName]) -> HeaderValue {
+    let headers: Vec<&str> = headers.iter().map(HeaderName::as_str).collect();
+    HeaderValue::from_str(&headers.join(", ")).unwrap()
+}
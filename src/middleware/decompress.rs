@@ -0,0 +1,109 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Request body decompression.
+
+use crate::context::Context;
+use crate::factory::BoxFuture;
+use crate::middleware::{Middleware, Next};
+use crate::response::HttpResponse;
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder};
+use futures_util::TryStreamExt;
+use hyper::header::{self, HeaderValue};
+use hyper::Body;
+use std::io;
+use tokio::io::{AsyncBufRead, AsyncReadExt, BufReader};
+use tokio_util::io::StreamReader;
+
+/// Decodes request bodies sent with `Content-Encoding: gzip`, `deflate` or
+/// `br` before the handler sees them.
+///
+/// The decoded body is buffered and limited to 2 MiB by default, counted
+/// after decompression so a small compressed upload can't expand without
+/// bound. Larger bodies get `413 Payload Too Large`, corrupt ones
+/// `400 Bad Request` and unknown encodings `415 Unsupported Media Type`.
+#[derive(Clone)]
+pub struct Decompress {
+    limit: u64,
+}
+
+type Reader = Box<dyn AsyncBufRead + Send + Sync + Unpin>;
+
+impl Decompress {
+    pub const fn new() -> Self {
+        Self {
+            limit: 2 * 1024 * 1024,
+        }
+    }
+
+    /// The largest decoded body accepted, in bytes.
+    pub fn limit(&mut self, bytes: u64) -> &mut Self {
+        self.limit = bytes;
+        self
+    }
+}
+
+impl Default for Decompress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Decompress {
+    fn call(&self, mut c: Context, next: Next) -> BoxFuture<HttpResponse> {
+        let codings: Vec<String> = c
+            .req
+            .headers()
+            .get_all(header::CONTENT_ENCODING)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim().to_ascii_lowercase())
+            .filter(|v| !v.is_empty() && v != "identity")
+            .collect();
+        if codings.is_empty() {
+            return next.run(c);
+        }
+        let limit = self.limit;
+        Box::pin(async move {
+            let body = std::mem::take(c.req.body_mut());
+            let mut reader: Reader = Box::new(StreamReader::new(TryStreamExt::map_err(
+                body,
+                io::Error::other,
+            )));
+            // Codings are listed in the order they were applied.
+            for coding in codings.iter().rev() {
+                reader = match coding.as_str() {
+                    "gzip" | "x-gzip" => Box::new(BufReader::new(GzipDecoder::new(reader))),
+                    "deflate" => Box::new(BufReader::new(ZlibDecoder::new(reader))),
+                    "br" => Box::new(BufReader::new(BrotliDecoder::new(reader))),
+                    _ => return HttpResponse::UnsupportedMediaType().empty(),
+                };
+            }
+            let mut decoded = Vec::new();
+            if let Err(e) = reader.take(limit + 1).read_to_end(&mut decoded).await {
+                debug!("Failed to decode request body: {}", e);
+                return HttpResponse::BadRequest().empty();
+            }
+            if decoded.len() as u64 > limit {
+                return HttpResponse::PayloadTooLarge().empty();
+            }
+            let headers = c.req.headers_mut();
+            headers.remove(header::CONTENT_ENCODING);
+            headers.insert(header::CONTENT_LENGTH, HeaderValue::from(decoded.len()));
+            *c.req.body_mut() = Body::from(decoded);
+            next.run(c).await
+        })
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Code running around handlers.
+//!
+//! Middleware is attached with `Server::wrap`, `Router::wrap`, `App::wrap` or
+//! `SubApp::wrap` and runs in that order: server middleware first, then the
+//! router's, the app's, the route's, and finally the handler. Within each
+//! level middleware runs in the order it was added.
+
+pub mod access_log;
+pub mod compress;
+pub mod concurrency;
+pub mod cors;
+pub mod decompress;
+pub mod rate_limit;
+pub mod request_id;
+
+use crate::context::Context;
+use crate::factory::{BoxFuture, BoxService, Service};
+use crate::response::HttpResponse;
+use crate::timeout::Timeout;
+use futures_util::FutureExt;
+use std::any::Any;
+use std::future::{ready, Future};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Runs before and after the rest of the chain.
+///
+/// Call `next.run(c)` to continue with the next middleware or the handler,
+/// or return a response without calling it to short-circuit.
+pub trait Middleware: Send + Sync + 'static {
+    fn call(&self, c: Context, next: Next) -> BoxFuture<HttpResponse>;
+}
+
+impl<F, R> Middleware for F
+where
+    F: Fn(Context, Next) -> R + Send + Sync + 'static,
+    R: Future<Output = HttpResponse> + Send + Sync + 'static,
+{
+    fn call(&self, c: Context, next: Next) -> BoxFuture<HttpResponse> {
+        Box::pin((self)(c, next))
+    }
+}
+
+/// The rest of the chain after the running middleware.
+pub struct Next {
+    /// Remaining middleware, the next one last.
+    middleware: Vec<Arc<dyn Middleware>>,
+    service: BoxService<HttpResponse>,
+    timeout: Option<Timeout>,
+}
+
+impl Next {
+    pub(crate) fn new(
+        mut middleware: Vec<Arc<dyn Middleware>>,
+        service: BoxService<HttpResponse>,
+        timeout: Option<Timeout>,
+    ) -> Self {
+        middleware.reverse();
+        Self {
+            middleware,
+            service,
+            timeout,
+        }
+    }
+
+    pub fn run(mut self, c: Context) -> BoxFuture<HttpResponse> {
+        let middleware = match self.middleware.pop() {
+            Some(middleware) => middleware,
+            None => {
+                let timeout = self.timeout;
+                let service = self.service;
+                return catch_panic(move || match timeout {
+                    Some(timeout) => with_timeout(timeout, service.call(c)),
+                    None => service.call(c),
+                });
+            }
+        };
+        middleware.call(c, self)
+    }
+}
+
+/// Why the handler did not answer, left in the response extensions for the
+/// server to log together with the request.
+pub(crate) enum Failure {
+    Panicked(String),
+    TimedOut(Duration),
+}
+
+/// Cancel `handler` once `timeout` has passed.
+fn with_timeout(timeout: Timeout, handler: BoxFuture<HttpResponse>) -> BoxFuture<HttpResponse> {
+    Box::pin(async move {
+        match tokio::time::timeout(timeout.duration(), handler).await {
+            Ok(res) => res,
+            Err(_) => failed(timeout.response(), Failure::TimedOut(timeout.duration())),
+        }
+    })
+}
+
+/// Answer `500 Internal Server Error` when `call`, or the future it returns,
+/// panics, instead of tearing down the connection.
+pub(crate) fn catch_panic<F>(call: F) -> BoxFuture<HttpResponse>
+where
+    F: FnOnce() -> BoxFuture<HttpResponse>,
+{
+    let future = match panic::catch_unwind(AssertUnwindSafe(call)) {
+        Ok(future) => future,
+        Err(panic) => return Box::pin(ready(panicked(panic))),
+    };
+    Box::pin(async move {
+        match AssertUnwindSafe(future).catch_unwind().await {
+            Ok(res) => res,
+            Err(panic) => panicked(panic),
+        }
+    })
+}
+
+fn panicked(panic: Box<dyn Any + Send>) -> HttpResponse {
+    let message = match panic.downcast_ref::<&str>() {
+        Some(message) => String::from(*message),
+        None => panic
+            .downcast_ref::<String>()
+            .map_or_else(|| String::from("Box<dyn Any>"), String::clone),
+    };
+    failed(
+        HttpResponse::InternalServerError().empty(),
+        Failure::Panicked(message),
+    )
+}
+
+fn failed(res: HttpResponse, failure: Failure) -> HttpResponse {
+    let mut res = res.res();
+    res.extensions_mut().insert(failure);
+    HttpResponse::from_builder(res)
+}
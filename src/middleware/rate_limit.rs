@@ -0,0 +1,393 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Request rate limiting.
+
+use crate::context::Context;
+use crate::extract::{FromRequest, RemoteAddr};
+use crate::factory::BoxFuture;
+use crate::middleware::{Middleware, Next};
+use crate::response::HttpResponse;
+use fnv::FnvHashMap;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limits how often each client may call the wrapped routes, answering
+/// `429 Too Many Requests` with `Retry-After` once the quota is used up.
+///
+/// Clients are told their quota with the `RateLimit-Limit`,
+/// `RateLimit-Remaining` and `RateLimit-Reset` headers. Requests are keyed by
+/// the remote IP address unless `key_header` or `key_with` say otherwise;
+/// requests without a key are not limited.
+#[derive(Clone)]
+pub struct RateLimit {
+    quota: Quota,
+    key: Arc<KeyFn>,
+    store: Arc<dyn RateLimitStore>,
+}
+
+type KeyFn = dyn Fn(&Context) -> Option<String> + Send + Sync;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quota {
+    /// Bursts of up to `capacity` requests, refilled at `capacity` per `period`.
+    TokenBucket { capacity: u64, period: Duration },
+    /// At most `limit` requests in any `window`, estimated from the counts of
+    /// the current and previous fixed windows.
+    SlidingWindow { limit: u64, window: Duration },
+}
+
+/// The outcome of counting a request against a quota.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Until the quota is fully available again.
+    pub reset: Duration,
+    /// Until the next request would be allowed, when this one wasn't.
+    pub retry_after: Option<Duration>,
+}
+
+/// Keeps the request counts of every key.
+pub trait RateLimitStore: Send + Sync + 'static {
+    /// Count a request by `key` against `quota`.
+    fn hit(&self, key: &str, quota: &Quota) -> BoxFuture<Decision>;
+}
+
+/// A `RateLimitStore` in process memory, shared by the clones of a
+/// `RateLimit` but not between processes.
+pub struct MemoryStore {
+    start: Instant,
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    entries: FnvHashMap<String, Entry>,
+    hits: u64,
+}
+
+#[derive(Clone, Copy)]
+enum Entry {
+    Bucket {
+        tokens: f64,
+        /// Seconds since the store was created.
+        updated: f64,
+    },
+    Window {
+        start: f64,
+        current: u64,
+        previous: u64,
+    },
+}
+
+/// How many hits between sweeps of idle keys.
+const SWEEP_EVERY: u64 = 4096;
+
+impl RateLimit {
+    pub fn new(quota: Quota) -> Self {
+        let (Quota::TokenBucket { capacity, period }
+        | Quota::SlidingWindow {
+            limit: capacity,
+            window: period,
+        }) = quota;
+        assert!(
+            capacity > 0 && !period.is_zero(),
+            "Invalid rate limit quota."
+        );
+        Self {
+            quota,
+            key: Arc::new(|c: &Context| match c.req.extensions().get::<RemoteAddr>() {
+                Some(RemoteAddr(Some(addr))) => Some(addr.ip().to_string()),
+                _ => None,
+            }),
+            store: Arc::new(MemoryStore::new()),
+        }
+    }
+
+    /// Key requests by the value of a header, such as an API key.
+    pub fn key_header(&mut self, name: &str) -> &mut Self {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("Invalid header name.");
+        self.key = Arc::new(move |c: &Context| {
+            let value = c.req.headers().get(&name)?.to_str().ok()?;
+            Some(String::from(value))
+        });
+        self
+    }
+
+    /// Key requests by a value derived from an extractor, e.g. a user id from
+    /// the path params.
+    pub fn key_with<T, F>(&mut self, key: F) -> &mut Self
+    where
+        T: FromRequest,
+        F: Fn(T) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(move |c: &Context| key(T::from_request(c).ok()?));
+        self
+    }
+
+    /// Keep counts in `store` instead of a `MemoryStore`.
+    pub fn store<S: RateLimitStore>(&mut self, store: S) -> &mut Self {
+        self.store = Arc::new(store);
+        self
+    }
+}
+
+impl Middleware for RateLimit {
+    fn call(&self, c: Context, next: Next) -> BoxFuture<HttpResponse> {
+        let key = match (self.key)(&c) {
+            Some(key) => key,
+            None => return next.run(c),
+        };
+        let decision = self.store.hit(&key, &self.quota);
+        Box::pin(async move {
+            let decision = decision.await;
+            let mut res = if decision.allowed {
+                next.run(c).await.res()
+            } else {
+                debug!("Rate limit exceeded for {}", key);
+                HttpResponse::TooManyRequests()
+                    .text("Too Many Requests")
+                    .res()
+            };
+            decision.write_headers(res.headers_mut());
+            HttpResponse::from_builder(res)
+        })
+    }
+}
+
+impl Decision {
+    fn write_headers(&self, headers: &mut HeaderMap) {
+        let mut set = |name: &'static str, value: u64| {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+        };
+        set("ratelimit-limit", self.limit);
+        set("ratelimit-remaining", self.remaining);
+        set("ratelimit-reset", seconds(self.reset));
+        if let Some(retry_after) = self.retry_after {
+            set("retry-after", seconds(retry_after).max(1));
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            state: Mutex::new(MemoryState::default()),
+        }
+    }
+
+    fn decide(&self, key: &str, quota: &Quota) -> Decision {
+        let now = self.start.elapsed().as_secs_f64();
+        let mut state = self.state.lock().unwrap();
+        state.hits = (state.hits + 1) % SWEEP_EVERY;
+        if state.hits == 0 {
+            state.entries.retain(|_, entry| !entry.idle(quota, now));
+        }
+        let entry = state
+            .entries
+            .entry(String::from(key))
+            .or_insert_with(|| Entry::new(quota, now));
+        entry.hit(quota, now)
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn hit(&self, key: &str, quota: &Quota) -> BoxFuture<Decision> {
+        let decision = self.decide(key, quota);
+        Box::pin(async move { decision })
+    }
+}
+
+impl Entry {
+    fn new(quota: &Quota, now: f64) -> Self {
+        match *quota {
+            Quota::TokenBucket { capacity, .. } => Entry::Bucket {
+                tokens: capacity as f64,
+                updated: now,
+            },
+            Quota::SlidingWindow { .. } => Entry::Window {
+                start: now,
+                current: 0,
+                previous: 0,
+            },
+        }
+    }
+
+    /// Whether the entry is back to a fresh state and can be dropped.
+    fn idle(&self, quota: &Quota, now: f64) -> bool {
+        match (*self, *quota) {
+            (Entry::Bucket { tokens, updated }, Quota::TokenBucket { capacity, period }) => {
+                let rate = capacity as f64 / period.as_secs_f64();
+                tokens + (now - updated) * rate >= capacity as f64
+            }
+            (Entry::Window { start, .. }, Quota::SlidingWindow { window, .. }) => {
+                now - start >= 2.0 * window.as_secs_f64()
+            }
+            _ => true,
+        }
+    }
+
+    fn hit(&mut self, quota: &Quota, now: f64) -> Decision {
+        if let (Entry::Bucket { .. }, Quota::SlidingWindow { .. })
+        | (Entry::Window { .. }, Quota::TokenBucket { .. }) = (&*self, quota)
+        {
+            *self = Entry::new(quota, now);
+        }
+        match (self, *quota) {
+            (Entry::Bucket { tokens, updated }, Quota::TokenBucket { capacity, period }) => {
+                let rate = capacity as f64 / period.as_secs_f64();
+                *tokens = (*tokens + (now - *updated) * rate).min(capacity as f64);
+                *updated = now;
+                let allowed = *tokens >= 1.0;
+                if allowed {
+                    *tokens -= 1.0;
+                }
+                Decision {
+                    allowed,
+                    limit: capacity,
+                    remaining: *tokens as u64,
+                    reset: secs((capacity as f64 - *tokens) / rate),
+                    retry_after: (!allowed).then(|| secs((1.0 - *tokens) / rate)),
+                }
+            }
+            (
+                Entry::Window {
+                    start,
+                    current,
+                    previous,
+                },
+                Quota::SlidingWindow { limit, window },
+            ) => {
+                let window = window.as_secs_f64();
+                let elapsed_windows = ((now - *start) / window).floor();
+                if elapsed_windows >= 1.0 {
+                    *previous = if elapsed_windows < 2.0 { *current } else { 0 };
+                    *current = 0;
+                    *start += elapsed_windows * window;
+                }
+                let into = now - *start;
+                let weight = 1.0 - into / window;
+                let estimate = *previous as f64 * weight + *current as f64;
+                let allowed = estimate + 1.0 <= limit as f64;
+                if allowed {
+                    *current += 1;
+                }
+                let estimate = *previous as f64 * weight + *current as f64;
+                let retry_after = (!allowed).then(|| {
+                    let target = (limit - 1) as f64;
+                    if *current as f64 <= target && *previous > 0 {
+                        // Wait for the previous window's share to shrink.
+                        let weight = (target - *current as f64) / *previous as f64;
+                        secs(window * (1.0 - weight) - into)
+                    } else {
+                        // Wait for this window to end and its share to shrink.
+                        let weight = target / *current as f64;
+                        secs(window - into + window * (1.0 - weight))
+                    }
+                });
+                Decision {
+                    allowed,
+                    limit,
+                    remaining: (limit as f64 - estimate).max(0.0) as u64,
+                    reset: secs(window - into),
+                    retry_after,
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn secs(secs: f64) -> Duration {
+    Duration::from_secs_f64(secs.max(0.0))
+}
+
+/// Whole seconds, rounded up.
+fn seconds(d: Duration) -> u64 {
+    d.as_secs() + u64::from(d.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, Quota};
+    use std::time::Duration;
+
+    const QUOTA: Quota = Quota::SlidingWindow {
+        limit: 10,
+        window: Duration::from_secs(10),
+    };
+
+    /// How many of `n` hits at `now` are allowed.
+    fn allowed(entry: &mut Entry, now: f64, n: usize) -> usize {
+        (0..n).filter(|_| entry.hit(&QUOTA, now).allowed).count()
+    }
+
+    #[test]
+    fn window_fills_up() {
+        let mut entry = Entry::new(&QUOTA, 0.0);
+        let first = entry.hit(&QUOTA, 0.0);
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 9);
+        assert_eq!(first.reset, Duration::from_secs(10));
+        assert_eq!(allowed(&mut entry, 1.0, 10), 9);
+        let denied = entry.hit(&QUOTA, 2.0);
+        assert!(!denied.allowed);
+        assert_eq!(denied.remaining, 0);
+        assert!(denied.retry_after.is_some());
+    }
+
+    #[test]
+    fn previous_window_counts_by_overlap() {
+        let mut entry = Entry::new(&QUOTA, 0.0);
+        assert_eq!(allowed(&mut entry, 0.0, 10), 10);
+        // Halfway through the next window half of the previous count remains.
+        assert_eq!(allowed(&mut entry, 15.0, 10), 5);
+
+        let denied = entry.hit(&QUOTA, 15.0);
+        let retry = denied.retry_after.unwrap().as_secs_f64();
+        assert!((retry - 1.0).abs() < 1e-6);
+        assert!(!entry.hit(&QUOTA, 15.0 + retry - 0.01).allowed);
+        assert!(entry.hit(&QUOTA, 15.0 + retry + 0.01).allowed);
+    }
+
+    #[test]
+    fn retry_after_full_current_window() {
+        let mut entry = Entry::new(&QUOTA, 0.0);
+        assert_eq!(allowed(&mut entry, 5.0, 10), 10);
+        let retry = entry.hit(&QUOTA, 5.0).retry_after.unwrap().as_secs_f64();
+        // The window ends at 10 and a tenth of its count has to drain after.
+        assert!((retry - 6.0).abs() < 1e-6);
+        assert!(!entry.hit(&QUOTA, 5.0 + retry - 0.01).allowed);
+        assert!(entry.hit(&QUOTA, 5.0 + retry + 0.01).allowed);
+    }
+
+    #[test]
+    fn counts_expire_after_two_windows() {
+        let mut entry = Entry::new(&QUOTA, 0.0);
+        assert_eq!(allowed(&mut entry, 0.0, 10), 10);
+        assert!(!entry.idle(&QUOTA, 19.0));
+        assert!(entry.idle(&QUOTA, 20.0));
+        assert_eq!(allowed(&mut entry, 25.0, 11), 10);
+    }
+}
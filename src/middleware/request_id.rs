@@ -0,0 +1,160 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Request ids for tracing a request across services.
+
+use crate::context::Context;
+use crate::extract::RequestId;
+use crate::factory::BoxFuture;
+use crate::middleware::{Middleware, Next};
+use crate::response::HttpResponse;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, Request, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use uuid::Uuid;
+
+const MAX_LEN: usize = 128;
+
+/// Gives every request an id, available through the `RequestId` extractor
+/// and echoed in the response.
+///
+/// An incoming `X-Request-Id` is kept if it is at most 128 characters of
+/// letters, digits and `-_.:+/=`; otherwise a UUID v4 is generated.
+///
+/// Added with `Server::layer` it runs before routing, so mounted services and
+/// the server's own log lines see the id too. With `wrap` it only covers
+/// handlers; add it before `AccessLog` so the access log can include the id.
+#[derive(Clone)]
+pub struct SetRequestId {
+    header: HeaderName,
+}
+
+pub struct SetRequestIdService<S> {
+    inner: S,
+    ids: SetRequestId,
+}
+
+impl SetRequestId {
+    pub fn new() -> Self {
+        Self {
+            header: HeaderName::from_static("x-request-id"),
+        }
+    }
+
+    /// Read and echo the id in `name` instead of `X-Request-Id`.
+    pub fn header(&mut self, name: &str) -> &mut Self {
+        self.header = HeaderName::from_bytes(name.as_bytes()).expect("Invalid header name.");
+        self
+    }
+
+    /// The id of `req`, assigning one if an outer `SetRequestId` didn't.
+    fn assign(&self, req: &mut Request<Body>) -> HeaderValue {
+        if let Some(RequestId(id)) = req.extensions().get::<RequestId>() {
+            if let Ok(value) = HeaderValue::from_str(id) {
+                return value;
+            }
+        }
+        let incoming = req
+            .headers()
+            .get(&self.header)
+            .and_then(|v| v.to_str().ok())
+            .filter(|id| valid(id))
+            .map(String::from);
+        let id = incoming.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let value = HeaderValue::from_str(&id).unwrap();
+        req.headers_mut().insert(self.header.clone(), value.clone());
+        req.extensions_mut().insert(RequestId(id));
+        value
+    }
+}
+
+impl Default for SetRequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for SetRequestId {
+    fn call(&self, mut c: Context, next: Next) -> BoxFuture<HttpResponse> {
+        let id = self.assign(&mut c.req);
+        let header = self.header.clone();
+        Box::pin(async move {
+            let mut res = next.run(c).await.res();
+            res.headers_mut().insert(header, id);
+            HttpResponse::from_builder(res)
+        })
+    }
+}
+
+impl<S> tower::Layer<S> for SetRequestId {
+    type Service = SetRequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SetRequestIdService {
+            inner,
+            ids: self.clone(),
+        }
+    }
+}
+
+impl<S: Clone> Clone for SetRequestIdService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            ids: self.ids.clone(),
+        }
+    }
+}
+
+impl<S> tower::Service<Request<Body>> for SetRequestIdService<S>
+where
+    S: tower::Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let id = self.ids.assign(&mut req);
+        let header = self.ids.header.clone();
+        let res = self.inner.call(req);
+        Box::pin(async move {
+            let mut res = res.await?;
+            res.headers_mut().insert(header, id);
+            Ok(res)
+        })
+    }
+}
+
+fn valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:+/=".contains(&b))
+}
+
+/// The id of `req` for log lines, `-` when it has none.
+pub(crate) fn request_id(req: &Request<Body>) -> &str {
+    req.extensions()
+        .get::<RequestId>()
+        .map_or("-", |id| id.0.as_str())
+}
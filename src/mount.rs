@@ -0,0 +1,89 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use hyper::service::Service;
+use hyper::{Body, Request, Response, Uri};
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::Mutex;
+
+type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+type ResponseFuture = Pin<Box<dyn Future<Output = Result<Response<Body>, Error>> + Send>>;
+
+/// A hyper or tower service receiving every request under a path prefix.
+pub struct ServiceMount {
+    prefix: String,
+    strip_prefix: bool,
+    call: Box<dyn Fn(Request<Body>) -> ResponseFuture + Send + Sync>,
+}
+
+impl ServiceMount {
+    pub(crate) fn new<S>(prefix: &str, service: S) -> Self
+    where
+        S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<Error>,
+    {
+        // Behind a lock so that services which aren't `Sync`, such as
+        // `BoxCloneService`, can be shared; each call works on a clone.
+        let service = Mutex::new(service);
+        let call = move |req| {
+            let mut service = service.lock().unwrap().clone();
+            Box::pin(async move {
+                poll_fn(|cx| service.poll_ready(cx))
+                    .await
+                    .map_err(Into::into)?;
+                service.call(req).await.map_err(Into::into)
+            }) as ResponseFuture
+        };
+        Self {
+            prefix: String::from(prefix.trim_end_matches('/')),
+            strip_prefix: false,
+            call: Box::new(call),
+        }
+    }
+
+    /// Remove the prefix from the path before forwarding, so the service sees
+    /// `/metrics` rather than `/legacy/metrics`.
+    pub fn strip_prefix(&mut self) -> &mut Self {
+        self.strip_prefix = true;
+        self
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        path.strip_prefix(self.prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    pub(crate) fn call(&self, mut req: Request<Body>) -> ResponseFuture {
+        if self.strip_prefix {
+            let path = &req.uri().path()[self.prefix.len()..];
+            let path = if path.is_empty() { "/" } else { path };
+            let path_and_query = match req.uri().query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => String::from(path),
+            };
+            let mut parts = req.uri().clone().into_parts();
+            parts.path_and_query = path_and_query.parse().ok();
+            if let Ok(uri) = Uri::from_parts(parts) {
+                *req.uri_mut() = uri;
+            }
+        }
+        (self.call)(req)
+    }
+}
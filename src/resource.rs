@@ -0,0 +1,215 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::context::Context;
+use crate::factory::{BoxFuture, Service, ServiceFactory};
+use crate::response::HttpResponse;
+use crate::router::Params;
+use hyper::header::{self, HeaderValue};
+use hyper::{Method, Request, StatusCode};
+use std::future::{ready, Ready};
+use std::sync::Arc;
+
+/// A CRUD resource registered with `App::resource`.
+///
+/// For `App::resource("/posts", PostResource)` the actions are routed as
+///
+/// | Method | Path         | Action    |
+/// |--------|--------------|-----------|
+/// | GET    | `/posts`     | `index`   |
+/// | POST   | `/posts`     | `create`  |
+/// | GET    | `/posts/:id` | `show`    |
+/// | PUT    | `/posts/:id` | `update`  |
+/// | PATCH  | `/posts/:id` | `patch`   |
+/// | DELETE | `/posts/:id` | `destroy` |
+///
+/// An action is implemented by overriding its method to return `Some`; the
+/// defaults return `None`. Actions that aren't implemented answer
+/// `405 Method Not Allowed` with an `Allow` header naming the implemented ones.
+///
+/// `App::resource` calls each method once with an empty request to find the
+/// implemented actions, so whether a method returns `Some` must not depend on
+/// the request, and the work belongs inside the returned future.
+pub trait Resource: Send + Sync + 'static {
+    fn index(&self, _c: Context) -> Option<BoxFuture<HttpResponse>> {
+        None
+    }
+
+    fn create(&self, _c: Context) -> Option<BoxFuture<HttpResponse>> {
+        None
+    }
+
+    fn show(&self, _c: Context) -> Option<BoxFuture<HttpResponse>> {
+        None
+    }
+
+    fn update(&self, _c: Context) -> Option<BoxFuture<HttpResponse>> {
+        None
+    }
+
+    fn patch(&self, _c: Context) -> Option<BoxFuture<HttpResponse>> {
+        None
+    }
+
+    fn destroy(&self, _c: Context) -> Option<BoxFuture<HttpResponse>> {
+        None
+    }
+}
+
+/// An action of a `Resource`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Action {
+    Index,
+    Create,
+    Show,
+    Update,
+    Patch,
+    Destroy,
+}
+
+impl Action {
+    pub(crate) const ALL: [Action; 6] = [
+        Action::Index,
+        Action::Create,
+        Action::Show,
+        Action::Update,
+        Action::Patch,
+        Action::Destroy,
+    ];
+
+    pub(crate) fn method(self) -> Method {
+        match self {
+            Action::Index | Action::Show => Method::GET,
+            Action::Create => Method::POST,
+            Action::Update => Method::PUT,
+            Action::Patch => Method::PATCH,
+            Action::Destroy => Method::DELETE,
+        }
+    }
+
+    /// Run the action on `resource`, `None` when it isn't implemented.
+    pub(crate) fn call<R: Resource>(
+        self,
+        resource: &R,
+        c: Context,
+    ) -> Option<BoxFuture<HttpResponse>> {
+        match self {
+            Action::Index => resource.index(c),
+            Action::Create => resource.create(c),
+            Action::Show => resource.show(c),
+            Action::Update => resource.update(c),
+            Action::Patch => resource.patch(c),
+            Action::Destroy => resource.destroy(c),
+        }
+    }
+
+    /// Whether `resource` implements the action.
+    pub(crate) fn implemented<R: Resource>(self, resource: &R) -> bool {
+        let c = Context::new(Request::default(), Params::default());
+        self.call(resource, c).is_some()
+    }
+
+    /// Whether the action acts on a single member, `/posts/:id`.
+    pub(crate) const fn member(self) -> bool {
+        !matches!(self, Action::Index | Action::Create)
+    }
+}
+
+/// One action of a shared resource, as a handler service.
+pub(crate) struct ResourceService<R> {
+    resource: Arc<R>,
+    action: Action,
+    /// The methods implemented for the action's path.
+    allow: HeaderValue,
+}
+
+impl<R> ResourceService<R> {
+    pub(crate) fn new(resource: Arc<R>, action: Action, allow: HeaderValue) -> Self {
+        Self {
+            resource,
+            action,
+            allow,
+        }
+    }
+}
+
+impl<R> Clone for ResourceService<R> {
+    fn clone(&self) -> Self {
+        Self {
+            resource: self.resource.clone(),
+            action: self.action,
+            allow: self.allow.clone(),
+        }
+    }
+}
+
+impl<R: Resource> ServiceFactory for ResourceService<R> {
+    type Response = HttpResponse;
+    type Service = Self;
+    type Future = Ready<Self::Service>;
+
+    fn new_service(&self) -> Self::Future {
+        ready(self.clone())
+    }
+}
+
+impl<R: Resource> Service for ResourceService<R> {
+    type Response = HttpResponse;
+    type Future = BoxFuture<HttpResponse>;
+
+    fn call(&self, c: Context) -> Self::Future {
+        let res = self.action.call(&*self.resource, c);
+        let allow = self.allow.clone();
+        Box::pin(async move {
+            let mut res = match res {
+                Some(res) => res.await.res(),
+                None => HttpResponse::MethodNotAllowed().empty().res(),
+            };
+            if res.status() == StatusCode::METHOD_NOT_ALLOWED {
+                res.headers_mut().insert(header::ALLOW, allow);
+            }
+            HttpResponse::from_builder(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Resource};
+    use crate::context::Context;
+    use crate::factory::BoxFuture;
+    use crate::response::HttpResponse;
+
+    struct Posts;
+
+    impl Resource for Posts {
+        fn show(&self, _c: Context) -> Option<BoxFuture<HttpResponse>> {
+            Some(Box::pin(async { HttpResponse::Ok().empty() }))
+        }
+
+        fn destroy(&self, _c: Context) -> Option<BoxFuture<HttpResponse>> {
+            Some(Box::pin(async { HttpResponse::Ok().empty() }))
+        }
+    }
+
+    #[test]
+    fn implemented_actions_follow_overrides() {
+        let implemented: Vec<Action> = Action::ALL
+            .iter()
+            .copied()
+            .filter(|a| a.implemented(&Posts))
+            .collect();
+        assert_eq!(implemented, [Action::Show, Action::Destroy]);
+    }
+}
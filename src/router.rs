@@ -12,24 +12,170 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::app::SubApp;
-use crate::extract::FromRequest;
+use crate::app::{App, SubApp};
+use crate::extract::{FromRequest, URL};
 use crate::factory::{factory, BoxServiceFactory, Handler, HandlerService};
+use crate::guard::request_host;
+use crate::middleware::Middleware;
+use crate::mount::ServiceMount;
 use crate::response::HttpResponse;
+use crate::timeout::Timeout;
+use crate::tree::Tree;
+use crate::version::Versioned;
 use fnv::FnvHashMap;
-use hyper::{Method, Response, StatusCode};
-use route_recognizer::{Params, Router as InternalRouter};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::Regex;
+use std::borrow::Cow;
+use std::fmt;
 use std::future::Future;
+use std::iter::{self, FromIterator};
+use std::ops::{Index, Range};
+use std::slice;
+use std::sync::Arc;
+
+/// Characters escaped when a value is placed in a single path segment.
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}')
+    .add(b'/');
+
+/// Like `SEGMENT`, but keeps `/` so wildcard values can span segments.
+const WILDCARD: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
 
 pub struct Router {
-    method_map: FnvHashMap<Method, InternalRouter<BoxServiceFactory<HttpResponse>>>,
+    routes: Vec<SubApp>,
+    /// Routes sharing a method and pattern, tried in registration order.
+    candidates: Vec<Vec<usize>>,
+    method_map: FnvHashMap<Method, Tree<usize>>,
+    hosts: Vec<VirtualHost>,
+    services: Vec<ServiceMount>,
+    trailing_slash: TrailingSlash,
+    middleware: Vec<Arc<dyn Middleware>>,
+    timeout: Option<Timeout>,
 
     not_found_handler: BoxServiceFactory<HttpResponse>,
+    redirect_handler: BoxServiceFactory<HttpResponse>,
+}
+
+/// How paths differing only by a trailing slash are treated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// `/echo` and `/echo/` are different routes.
+    #[default]
+    Strict,
+    /// Answer with a `308 Permanent Redirect` to the registered form.
+    Redirect,
+    /// Serve the registered route for both forms.
+    MatchBoth,
+}
+
+/// A separate route table selected by the `Host` header.
+///
+/// A pattern starting with `*` (e.g. `*.example.com`) matches any subdomain
+/// and exposes it as the `subdomain` path param.
+struct VirtualHost {
+    pattern: String,
+    router: Router,
+}
+
+/// Path params of a matched route, in pattern order.
+///
+/// Values of a match are byte ranges into a single copy of the matched path.
+#[derive(Clone, Default)]
+pub struct Params {
+    path: String,
+    map: Vec<(Arc<str>, Value)>,
+}
+
+#[derive(Clone)]
+enum Value {
+    Path(Range<usize>),
+    Owned(String),
+}
+
+pub struct Iter<'a> {
+    path: &'a str,
+    inner: slice::Iter<'a, (Arc<str>, Value)>,
 }
 
 pub struct RouterMatch<'a> {
     pub handler: &'a BoxServiceFactory<HttpResponse>,
     pub params: Params,
+    /// The matched route, `None` when falling back to a built-in handler.
+    pub route: Option<&'a SubApp>,
+    /// Middleware to run around `handler`, outermost first.
+    pub middleware: Vec<Arc<dyn Middleware>>,
+    /// The route's timeout, or the router's.
+    pub timeout: Option<Timeout>,
+}
+
+/// A check on a path param, written after the param name in a pattern:
+/// `/users/:id<int>`, `/keys/:key<uuid>` or `/files/:name<[a-z]+\.txt>`.
+#[derive(Clone, Debug)]
+pub enum Constraint {
+    /// One or more ASCII digits, with no sign and no length limit.
+    Int,
+    Uuid,
+    /// Must match the whole value.
+    Regex(Regex),
+}
+
+/// A registered route as listed by `Router::routes`.
+#[derive(Clone, Debug)]
+pub struct RouteInfo<'a> {
+    pub host: Option<&'a str>,
+    pub method: &'a Method,
+    /// Full pattern, including the mount prefix.
+    pub pattern: &'a str,
+    /// Type name of the handler function.
+    pub handler: &'a str,
+    pub name: Option<&'a str>,
+    pub tags: &'a [String],
+}
+
+/// Why two registrations conflict.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Same method and pattern, and the earlier route has no guards or
+    /// constraints, so the later one is unreachable.
+    Duplicate,
+    /// Both patterns match some path and neither is more specific in every
+    /// segment, so the leftmost static segment decides which one wins.
+    Ambiguous,
+}
+
+/// Two registrations that shadow each other.
+#[derive(Clone, Debug)]
+pub struct RouteConflict {
+    pub kind: ConflictKind,
+    pub method: Method,
+    pub first: String,
+    pub second: String,
+}
+
+/// Reverse URL generation for named routes.
+#[derive(Clone, Debug, Default)]
+pub struct UrlFor {
+    patterns: Arc<FnvHashMap<String, String>>,
 }
 
 impl Default for Router {
@@ -41,49 +187,678 @@ impl Default for Router {
 impl Router {
     pub fn new() -> Self {
         Self {
+            routes: Vec::new(),
+            candidates: Vec::new(),
             method_map: FnvHashMap::default(),
+            hosts: Vec::new(),
+            services: Vec::new(),
+            trailing_slash: TrailingSlash::default(),
+            middleware: Vec::new(),
+            timeout: None,
             not_found_handler: factory(HandlerService::new(not_found_handler)),
+            redirect_handler: factory(HandlerService::new(redirect_handler)),
         }
     }
 
-    pub fn add<F, T, R>(&mut self, path: &str, method: Method, handler: F)
+    pub fn set_trailing_slash(&mut self, policy: TrailingSlash) {
+        self.trailing_slash = policy;
+    }
+
+    /// Run `middleware` around every handler of this router, including the
+    /// not found and redirect handlers.
+    pub fn wrap<M: Middleware>(&mut self, middleware: M) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Cancel handlers running longer than `timeout`, unless their app or
+    /// route sets one.
+    pub fn set_timeout<T: Into<Timeout>>(&mut self, timeout: T) {
+        self.timeout = Some(timeout.into());
+    }
+
+    pub fn add<F, T, R>(&mut self, path: &str, method: Method, handler: F) -> &mut SubApp
     where
         F: Handler<T, R>,
         T: FromRequest,
         R: Future<Output = HttpResponse> + Send + Sync + 'static,
     {
-        self.method_map
-            .entry(method)
-            .or_insert_with(InternalRouter::new)
-            .add(path, factory(HandlerService::new(handler)));
+        self.insert(SubApp::new(String::from(path), method, handler))
+    }
+
+    pub fn mount(&mut self, path: &str, mut sub_app: SubApp) -> &mut SubApp {
+        sub_app.path = format!("{}{}", path, sub_app.path);
+        sub_app.prefix = String::from(path);
+        self.insert(sub_app)
+    }
+
+    /// Add every route of `app` under `mount_point`.
+    pub fn mount_app(&mut self, mount_point: &str, app: App) {
+        for subapp in app.apps() {
+            self.mount(mount_point, subapp);
+        }
+    }
+
+    /// Mount every version of a `Versioned` API at `mount_point`.
+    pub fn mount_versioned(&mut self, mount_point: &str, versioned: Versioned) {
+        for subapp in versioned.apps() {
+            self.mount(mount_point, subapp);
+        }
+    }
+
+    /// Forward every request under `prefix` to `service` instead of the routes.
+    pub fn mount_service<S>(&mut self, prefix: &str, service: S) -> &mut ServiceMount
+    where
+        S: hyper::service::Service<Request<Body>, Response = Response<Body>>
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        self.services.push(ServiceMount::new(prefix, service));
+        self.services.last_mut().unwrap()
     }
 
-    pub fn mount(&mut self, path: &str, sub_app: SubApp) {
-        self.method_map
-            .entry(sub_app.method)
-            .or_insert_with(InternalRouter::new)
-            .add(&format!("{}{}", path, sub_app.path), sub_app.handler);
+    /// The mounted service with the longest prefix covering the request path,
+    /// looking at the matching virtual host's services before this router's.
+    pub fn service_for(&self, req: &Request<Body>) -> Option<&ServiceMount> {
+        let path = req.uri().path();
+        self.virtual_host(req)
+            .and_then(|(h, _)| longest_prefix(&h.router.services, path))
+            .or_else(|| longest_prefix(&self.services, path))
     }
 
-    pub fn route(&self, path: &str, method: &Method) -> RouterMatch {
-        if let Some(m) = self
+    /// The route table used for requests to `host`, created on first use.
+    pub fn host(&mut self, host: &str) -> &mut Router {
+        let host = host.to_ascii_lowercase();
+        let index = match self.hosts.iter().position(|h| h.pattern == host) {
+            Some(index) => index,
+            None => {
+                self.hosts.push(VirtualHost {
+                    pattern: host,
+                    router: Router::new(),
+                });
+                self.hosts.len() - 1
+            }
+        };
+        &mut self.hosts[index].router
+    }
+
+    fn insert(&mut self, mut route: SubApp) -> &mut SubApp {
+        let index = self.routes.len();
+        let (pattern, constraints) = split_constraints(&route.path);
+        route.constraints = constraints
+            .into_iter()
+            .map(|(name, spec)| (name, Constraint::parse(&spec)))
+            .collect();
+        route.param_names = segments(&pattern)
+            .into_iter()
+            .filter(|(_, s)| s.starts_with(':') || s.starts_with('*'))
+            .map(|(_, s)| Arc::from(&s[1..]))
+            .collect();
+        let slot = self
             .method_map
-            .get(method)
-            .and_then(|r| r.recognize(path).ok())
-        {
-            let mut params = Params::new();
-            params.clone_from(m.params());
+            .entry(route.method.clone())
+            .or_default()
+            .entry(&pattern);
+        match *slot {
+            Some(group) => self.candidates[group].push(index),
+            None => {
+                *slot = Some(self.candidates.len());
+                self.candidates.push(vec![index]);
+            }
+        }
+        route.pattern = pattern;
+        self.routes.push(route);
+        &mut self.routes[index]
+    }
+
+    pub fn route(&self, req: &Request<Body>) -> RouterMatch<'_> {
+        match self.virtual_host(req) {
+            Some((h, subdomain)) => {
+                let mut m = h.router.route_path(req, self.trailing_slash);
+                m.middleware.splice(0..0, self.middleware.iter().cloned());
+                if m.timeout.is_none() {
+                    m.timeout = self.timeout.clone();
+                }
+                if let Some(subdomain) = subdomain {
+                    m.params
+                        .insert(String::from("subdomain"), String::from(subdomain));
+                }
+                m
+            }
+            None => self.route_path(req, self.trailing_slash),
+        }
+    }
+
+    /// The virtual host serving `req` and the subdomain it captured, trying
+    /// exact hosts before wildcards.
+    fn virtual_host<'r>(&self, req: &'r Request<Body>) -> Option<(&VirtualHost, Option<&'r str>)> {
+        if self.hosts.is_empty() {
+            return None;
+        }
+        let host = request_host(req)?;
+        let wildcard = |h: &&VirtualHost| h.pattern.starts_with('*');
+        self.hosts
+            .iter()
+            .filter(|h| !wildcard(h))
+            .chain(self.hosts.iter().filter(wildcard))
+            .find_map(|h| h.matches(host).map(|subdomain| (h, subdomain)))
+    }
+
+    fn route_path(&self, req: &Request<Body>, trailing_slash: TrailingSlash) -> RouterMatch<'_> {
+        let path = req.uri().path();
+        let mut found = self.find(req, path);
+        if found.is_none() && trailing_slash != TrailingSlash::Strict && path != "/" {
+            let toggled = match path.strip_suffix('/') {
+                Some(path) => Cow::Borrowed(path),
+                None => Cow::Owned(format!("{}/", path)),
+            };
+            found = self.find(req, &toggled);
+            if found.is_some() && trailing_slash == TrailingSlash::Redirect {
+                return RouterMatch {
+                    handler: &self.redirect_handler,
+                    params: Params::new(),
+                    route: None,
+                    middleware: self.middleware.clone(),
+                    timeout: self.timeout.clone(),
+                };
+            }
+        }
+        if let Some((route, params)) = found {
             RouterMatch {
-                handler: m.handler(),
+                handler: &route.handler,
                 params,
+                route: Some(route),
+                middleware: self
+                    .middleware
+                    .iter()
+                    .chain(&route.middleware)
+                    .cloned()
+                    .collect(),
+                timeout: route.timeout.clone().or_else(|| self.timeout.clone()),
             }
         } else {
             RouterMatch {
                 handler: &self.not_found_handler,
                 params: Params::new(),
+                route: None,
+                middleware: self.middleware.clone(),
+                timeout: self.timeout.clone(),
             }
         }
     }
+
+    fn find(&self, req: &Request<Body>, path: &str) -> Option<(&SubApp, Params)> {
+        let tree = self.method_map.get(req.method())?;
+        let mut found = None;
+        tree.lookup(path, |&group, values| {
+            found = self.candidates[group]
+                .iter()
+                .map(|&i| &self.routes[i])
+                .find(|route| {
+                    let value = |name: &str| {
+                        let position = route.param_names.iter().position(|n| **n == *name);
+                        position.and_then(|i| values.get(i))
+                    };
+                    let valid = route.constraints.iter().all(|(name, constraint)| {
+                        value(name).into_iter().all(|v| constraint.matches(v))
+                    });
+                    valid && route.guards.iter().all(|g| g.check(req))
+                })
+                .map(|route| (route, Params::from_match(path, &route.param_names, values)));
+            found.is_some()
+        });
+        found
+    }
+
+    /// All registered routes in registration order, followed by those of virtual hosts.
+    pub fn routes(&self) -> impl Iterator<Item = RouteInfo<'_>> {
+        let mut routes = Vec::new();
+        self.collect_routes(None, &mut routes);
+        routes.into_iter()
+    }
+
+    fn collect_routes<'a>(&'a self, host: Option<&'a str>, routes: &mut Vec<RouteInfo<'a>>) {
+        routes.extend(self.routes.iter().map(|route| RouteInfo {
+            host,
+            method: &route.method,
+            pattern: &route.path,
+            handler: route.handler_name,
+            name: route.name.as_deref(),
+            tags: &route.tags,
+        }));
+        for h in &self.hosts {
+            h.router.collect_routes(Some(&h.pattern), routes);
+        }
+    }
+
+    /// Report registrations that shadow or overwrite each other.
+    pub fn conflicts(&self) -> Vec<RouteConflict> {
+        let mut conflicts = Vec::new();
+        self.collect_conflicts("", &mut conflicts);
+        conflicts
+    }
+
+    fn collect_conflicts(&self, host: &str, conflicts: &mut Vec<RouteConflict>) {
+        let conflict = |kind, first: &SubApp, second: &SubApp| RouteConflict {
+            kind,
+            method: first.method.clone(),
+            first: format!("{}{}", host, first.path),
+            second: format!("{}{}", host, second.path),
+        };
+        for group in &self.candidates {
+            for (i, later) in group.iter().enumerate().skip(1) {
+                let later = &self.routes[*later];
+                let earlier = group[..i]
+                    .iter()
+                    .map(|&e| &self.routes[e])
+                    .find(|e| e.guards.is_empty() && e.constraints.is_empty());
+                if let Some(earlier) = earlier {
+                    conflicts.push(conflict(ConflictKind::Duplicate, earlier, later));
+                }
+            }
+        }
+        for (i, a) in self.candidates.iter().enumerate() {
+            for b in &self.candidates[i + 1..] {
+                let (a, b) = (&self.routes[a[0]], &self.routes[b[0]]);
+                if a.method == b.method && ambiguous(&a.pattern, &b.pattern) {
+                    conflicts.push(conflict(ConflictKind::Ambiguous, a, b));
+                }
+            }
+        }
+        for h in &self.hosts {
+            h.router.collect_conflicts(&h.pattern, conflicts);
+        }
+    }
+
+    /// Collect the patterns of all named routes for reverse URL generation.
+    pub fn urls(&self) -> UrlFor {
+        let mut patterns = FnvHashMap::default();
+        self.collect_names(&mut patterns);
+        UrlFor {
+            patterns: Arc::new(patterns),
+        }
+    }
+
+    fn collect_names(&self, patterns: &mut FnvHashMap<String, String>) {
+        for host in &self.hosts {
+            host.router.collect_names(patterns);
+        }
+        for route in &self.routes {
+            if let Some(name) = &route.name {
+                patterns.insert(name.clone(), route.pattern.clone());
+            }
+        }
+    }
+}
+
+/// The service in `services` with the longest prefix covering `path`.
+fn longest_prefix<'s>(services: &'s [ServiceMount], path: &str) -> Option<&'s ServiceMount> {
+    services
+        .iter()
+        .filter(|s| s.matches(path))
+        .max_by_key(|s| s.prefix().len())
+}
+
+impl VirtualHost {
+    /// Returns the captured subdomain, if any, when `host` matches.
+    fn matches<'h>(&self, host: &'h str) -> Option<Option<&'h str>> {
+        match self.pattern.strip_prefix('*') {
+            Some(suffix) => {
+                let split = host.len().checked_sub(suffix.len()).filter(|&i| i > 0)?;
+                if host.is_char_boundary(split) && host[split..].eq_ignore_ascii_case(suffix) {
+                    Some(Some(&host[..split]))
+                } else {
+                    None
+                }
+            }
+            None if host.eq_ignore_ascii_case(&self.pattern) => Some(None),
+            None => None,
+        }
+    }
+}
+
+impl<'a> RouterMatch<'a> {
+    /// The pattern of the matched route, e.g. `/v1/echo/:msg`, suitable as a
+    /// low-cardinality label for logs and metrics.
+    pub fn pattern(&self) -> Option<&'a str> {
+        self.route.map(|route| route.pattern.as_str())
+    }
+}
+
+impl Params {
+    pub const fn new() -> Self {
+        Self {
+            path: String::new(),
+            map: Vec::new(),
+        }
+    }
+
+    /// The params of a match of `path`, with `values` borrowed from it.
+    fn from_match(path: &str, names: &[Arc<str>], values: &[&str]) -> Self {
+        if names.is_empty() {
+            return Self::new();
+        }
+        let map = names
+            .iter()
+            .zip(values)
+            .map(|(name, value)| {
+                let start = value.as_ptr() as usize - path.as_ptr() as usize;
+                (name.clone(), Value::Path(start..start + value.len()))
+            })
+            .collect();
+        Self {
+            path: String::from(path),
+            map,
+        }
+    }
+
+    /// Set `key` to `value`, replacing an earlier value.
+    pub fn insert(&mut self, key: String, value: String) {
+        match self.map.iter_mut().find(|(k, _)| **k == *key) {
+            Some((_, v)) => *v = Value::Owned(value),
+            None => self.map.push((Arc::from(key), Value::Owned(value))),
+        }
+    }
+
+    pub fn find(&self, key: &str) -> Option<&str> {
+        self.iter().find(|&(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            path: &self.path,
+            inner: self.map.iter(),
+        }
+    }
+}
+
+impl fmt::Debug for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for Params {}
+
+impl<'a> FromIterator<(&'a str, &'a str)> for Params {
+    fn from_iter<I: IntoIterator<Item = (&'a str, &'a str)>>(iter: I) -> Self {
+        let mut params = Self::new();
+        for (key, value) in iter {
+            params.insert(String::from(key), String::from(value));
+        }
+        params
+    }
+}
+
+impl Index<&str> for Params {
+    type Output = str;
+
+    fn index(&self, key: &str) -> &str {
+        self.find(key)
+            .unwrap_or_else(|| panic!("params[{}] did not exist", key))
+    }
+}
+
+impl<'a> IntoIterator for &'a Params {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.inner.next()?;
+        let value = match value {
+            Value::Path(range) => &self.path[range.clone()],
+            Value::Owned(value) => value.as_str(),
+        };
+        Some((key, value))
+    }
+}
+
+impl fmt::Display for RouteInfo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<7} {}{} {}",
+            self.method.as_str(),
+            self.host.unwrap_or_default(),
+            self.pattern,
+            self.handler
+        )?;
+        if let Some(name) = self.name {
+            write!(f, " name={}", name)?;
+        }
+        if !self.tags.is_empty() {
+            write!(f, " tags={}", self.tags.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RouteConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ConflictKind::Duplicate => write!(
+                f,
+                "{} {} duplicates {} {} registered earlier",
+                self.method, self.second, self.method, self.first
+            ),
+            ConflictKind::Ambiguous => write!(
+                f,
+                "{} {} and {} {} are ambiguous for some paths",
+                self.method, self.first, self.method, self.second
+            ),
+        }
+    }
+}
+
+impl UrlFor {
+    /// Build the URL of the route registered as `name`, substituting `:param`
+    /// and `*wildcard` segments from `params`.
+    ///
+    /// Returns `None` if no route has that name or a parameter is missing.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        let pattern = self.patterns.get(name)?;
+        let mut url = String::with_capacity(pattern.len());
+        for (i, (sep, segment)) in segments(pattern).into_iter().enumerate() {
+            if i > 0 {
+                url.push(sep);
+            }
+            let key = segment.get(1..).unwrap_or_default();
+            let value = || params.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+            match segment.as_bytes().first() {
+                Some(b':') => url.extend(utf8_percent_encode(value()?, SEGMENT)),
+                Some(b'*') => url.extend(utf8_percent_encode(value()?, WILDCARD)),
+                _ => url.push_str(segment),
+            }
+        }
+        Some(url)
+    }
+}
+
+impl Constraint {
+    /// Parse `int`, `uuid` or a regular expression.
+    pub fn parse(spec: &str) -> Self {
+        match spec {
+            "int" => Self::Int,
+            "uuid" => Self::Uuid,
+            _ => Self::Regex(
+                Regex::new(&format!("^(?:{})$", spec)).expect("Invalid route constraint."),
+            ),
+        }
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            Self::Int => !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()),
+            Self::Uuid => {
+                value.len() == 36
+                    && value.char_indices().all(|(i, c)| match i {
+                        8 | 13 | 18 | 23 => c == '-',
+                        _ => c.is_ascii_hexdigit(),
+                    })
+            }
+            Self::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+/// Split `:param<constraint>` segments into a plain pattern and the
+/// constraint specs by param name.
+fn split_constraints(pattern: &str) -> (String, Vec<(String, String)>) {
+    let mut plain = String::with_capacity(pattern.len());
+    let mut constraints = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    let mut segment_start = true;
+    while let Some(c) = chars.next() {
+        plain.push(c);
+        if !(segment_start && (c == ':' || c == '*')) {
+            segment_start = c == '/' || c == '.';
+            continue;
+        }
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '/' || c == '.' || c == '<' {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        plain.push_str(&name);
+        if chars.peek() == Some(&'<') {
+            chars.next();
+            let mut depth = 1;
+            let mut spec = String::new();
+            for c in chars.by_ref() {
+                match c {
+                    '<' => depth += 1,
+                    '>' => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    break;
+                }
+                spec.push(c);
+            }
+            constraints.push((name, spec));
+        }
+        segment_start = false;
+    }
+    (plain, constraints)
+}
+
+/// Split a pattern at `/` and `.`, pairing each segment with the separator
+/// before it.
+fn segments(pattern: &str) -> Vec<(char, &str)> {
+    let mut segments = Vec::new();
+    let mut sep = '/';
+    let mut start = 0;
+    let separators = pattern
+        .char_indices()
+        .filter(|(_, c)| *c == '/' || *c == '.');
+    for (end, next) in separators.chain(iter::once((pattern.len(), '/'))) {
+        segments.push((sep, &pattern[start..end]));
+        sep = next;
+        start = end + 1;
+    }
+    segments
+}
+
+/// Whether two wildcard-free patterns can match the same path without one
+/// being at least as specific as the other in every segment.
+fn ambiguous(a: &str, b: &str) -> bool {
+    let (a, b) = (segments(a), segments(b));
+    let dynamic = |s: &str| s.starts_with(':');
+    if a.len() != b.len() || a.iter().chain(&b).any(|(_, s)| s.starts_with('*')) {
+        return false;
+    }
+    let pairs = || a.iter().zip(&b);
+    let overlap =
+        pairs().all(|((sa, a), (sb, b))| sa == sb && (a == b || dynamic(a) || dynamic(b)));
+    let a_specific = pairs().all(|((_, a), (_, b))| !dynamic(a) || dynamic(b));
+    let b_specific = pairs().all(|((_, a), (_, b))| !dynamic(b) || dynamic(a));
+    overlap && !a_specific && !b_specific
+}
+
+/// Collapse duplicate slashes, remove dot-segments and normalize percent-encoding
+/// of a request path, keeping a trailing slash if present.
+pub fn normalize_path(path: &str) -> Cow<'_, str> {
+    let mut segments: Vec<Cow<'_, str>> = Vec::new();
+    let mut trailing = false;
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        let segment = normalize_percent(segment);
+        trailing = segment == "." || segment == "..";
+        match segment.as_ref() {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    trailing = trailing || path.ends_with('/');
+
+    let mut normalized = String::with_capacity(path.len());
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if trailing || segments.is_empty() {
+        normalized.push('/');
+    }
+    if normalized == path {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(normalized)
+    }
+}
+
+/// Decode percent-encoded unreserved characters and uppercase remaining escapes.
+fn normalize_percent(segment: &str) -> Cow<'_, str> {
+    if !segment.contains('%') {
+        return Cow::Borrowed(segment);
+    }
+    let bytes = segment.as_bytes();
+    let mut out = String::with_capacity(segment.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit));
+        let decoded = escape
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(b) if b.is_ascii_alphanumeric() || b"-._~".contains(&b) => {
+                out.push(b as char);
+                i += 3;
+            }
+            Some(b) => {
+                out.push_str(&format!("%{:02X}", b));
+                i += 3;
+            }
+            None => {
+                let len = segment[i..].chars().next().map_or(1, char::len_utf8);
+                out.push_str(&segment[i..i + len]);
+                i += len;
+            }
+        }
+    }
+    Cow::Owned(out)
 }
 
 async fn not_found_handler() -> HttpResponse {
@@ -94,3 +869,221 @@ async fn not_found_handler() -> HttpResponse {
             .unwrap(),
     )
 }
+
+async fn redirect_handler(url: URL) -> HttpResponse {
+    let path = url.path();
+    let mut location = match path.strip_suffix('/') {
+        Some(path) => String::from(path),
+        None => format!("{}/", path),
+    };
+    if let Some(query) = url.query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    HttpResponse::PermanentRedirect()
+        .header("Location", &location)
+        .empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ambiguous, normalize_path, split_constraints, ConflictKind, Router};
+    use crate::app::App;
+    use crate::guard::header;
+    use crate::response::HttpResponse;
+    use hyper::{Body, Method, Request};
+    use std::borrow::Cow;
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().empty()
+    }
+
+    fn get(path: &str) -> Request<Body> {
+        Request::get(path).body(Body::empty()).unwrap()
+    }
+
+    /// The pattern of the route matching a GET of `path`.
+    fn matched(router: &Router, path: &str) -> Option<String> {
+        router.route(&get(path)).pattern().map(String::from)
+    }
+
+    #[test]
+    fn normalized_paths_are_borrowed() {
+        for path in ["/", "/a/b", "/a/b/", "/caf%C3%A9", "/a%2Fb"] {
+            assert!(matches!(normalize_path(path), Cow::Borrowed(p) if p == path));
+        }
+    }
+
+    #[test]
+    fn collapses_slashes() {
+        assert_eq!(normalize_path(""), "/");
+        assert_eq!(normalize_path("//"), "/");
+        assert_eq!(normalize_path("//a///b"), "/a/b");
+        assert_eq!(normalize_path("/a//b//"), "/a/b/");
+    }
+
+    #[test]
+    fn removes_dot_segments() {
+        assert_eq!(normalize_path("/a/./b/../c"), "/a/c");
+        assert_eq!(normalize_path("/.."), "/");
+        assert_eq!(normalize_path("/a/../../b"), "/b");
+        assert_eq!(normalize_path("/a/b/.."), "/a/");
+        assert_eq!(normalize_path("/a/."), "/a/");
+        assert_eq!(normalize_path("/a/.b/c.."), "/a/.b/c..");
+    }
+
+    #[test]
+    fn normalizes_percent_encoding() {
+        assert_eq!(normalize_path("/%7Euser/%41%2d"), "/~user/A-");
+        assert_eq!(normalize_path("/caf%c3%a9"), "/caf%C3%A9");
+        assert_eq!(normalize_path("/a%2fb"), "/a%2Fb");
+        assert_eq!(normalize_path("/a/%2E%2E/b"), "/b");
+        assert_eq!(normalize_path("/%zz/%4"), "/%zz/%4");
+        assert_eq!(normalize_path("/%+1"), "/%+1");
+    }
+
+    #[test]
+    fn pattern_leaves_out_constraints() {
+        let mut router = Router::new();
+        router.add("/v1/echo/:msg<[a-z]+>", Method::GET, ok);
+        router.add("/v1/items/:id<int>.:ext", Method::GET, ok);
+        assert_eq!(
+            matched(&router, "/v1/echo/hi").as_deref(),
+            Some("/v1/echo/:msg")
+        );
+        assert_eq!(
+            matched(&router, "/v1/items/3.json").as_deref(),
+            Some("/v1/items/:id.:ext")
+        );
+    }
+
+    fn split(pattern: &str, plain: &str, constraints: &[(&str, &str)]) {
+        let constraints: Vec<_> = constraints
+            .iter()
+            .map(|&(name, spec)| (String::from(name), String::from(spec)))
+            .collect();
+        assert_eq!(
+            split_constraints(pattern),
+            (String::from(plain), constraints)
+        );
+    }
+
+    #[test]
+    fn constraint_with_nested_angle_brackets() {
+        split(
+            r"/archive/:date<(?P<year>\d{4})-(?P<month>\d{2})>/posts",
+            "/archive/:date/posts",
+            &[("date", r"(?P<year>\d{4})-(?P<month>\d{2})")],
+        );
+    }
+
+    #[test]
+    fn constraints_on_dot_separated_params() {
+        split(
+            "/files/:name<[a-z]+>.:ext<json|xml>",
+            "/files/:name.:ext",
+            &[("name", "[a-z]+"), ("ext", "json|xml")],
+        );
+        split("/files/:name.:ext", "/files/:name.:ext", &[]);
+    }
+
+    #[test]
+    fn int_constraint_falls_through_to_unconstrained_sibling() {
+        let mut router = Router::new();
+        router.add("/users/:id<int>", Method::GET, ok);
+        router.add("/users/:name", Method::GET, ok);
+        assert_eq!(matched(&router, "/users/42").as_deref(), Some("/users/:id"));
+        assert_eq!(
+            matched(&router, "/users/bob").as_deref(),
+            Some("/users/:name")
+        );
+        assert_eq!(
+            matched(&router, "/users/-1").as_deref(),
+            Some("/users/:name")
+        );
+    }
+
+    #[test]
+    fn static_segment_is_more_specific_than_param() {
+        assert!(!ambiguous("/users/me", "/users/:id"));
+        assert!(!ambiguous("/users/:id", "/users/me"));
+        assert!(!ambiguous("/users/:id", "/users/:name"));
+        assert!(!ambiguous("/users/:id", "/posts/:id"));
+    }
+
+    #[test]
+    fn crossed_static_segments_are_ambiguous() {
+        assert!(ambiguous("/users/:id/edit", "/users/me/:action"));
+        assert!(ambiguous("/users/me/:action", "/users/:id/edit"));
+
+        let mut router = Router::new();
+        router.add("/users/:id/edit", Method::GET, ok);
+        router.add("/users/me/:action", Method::GET, ok);
+        router.add("/users/me/:action", Method::POST, ok);
+        let conflicts = router.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, ConflictKind::Ambiguous);
+        assert_eq!(conflicts[0].method, Method::GET);
+        assert_eq!(conflicts[0].first, "/users/:id/edit");
+        assert_eq!(conflicts[0].second, "/users/me/:action");
+    }
+
+    #[test]
+    fn guarded_duplicate_is_not_a_conflict() {
+        let mut router = Router::new();
+        router
+            .add("/items", Method::GET, ok)
+            .guard(header("Accept", "text/csv"));
+        router.add("/items", Method::GET, ok);
+        assert!(router.conflicts().is_empty());
+
+        router.add("/items", Method::GET, ok);
+        let conflicts = router.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, ConflictKind::Duplicate);
+    }
+
+    #[test]
+    fn url_for_includes_mount_prefix() {
+        let mut app = App::new();
+        app.add("/posts/:id<int>", Method::GET, ok).name("post");
+        app.add("/", Method::GET, ok).name("home");
+        let mut router = Router::new();
+        router.mount_app("/api/v1", app);
+        let urls = router.urls();
+        assert_eq!(
+            urls.url_for("post", &[("id", "7")]).as_deref(),
+            Some("/api/v1/posts/7")
+        );
+        assert_eq!(urls.url_for("home", &[]).as_deref(), Some("/api/v1/"));
+    }
+
+    #[test]
+    fn url_for_encodes_params_and_wildcards() {
+        let mut router = Router::new();
+        router
+            .add("/users/:name/files/*path", Method::GET, ok)
+            .name("file");
+        let urls = router.urls();
+        assert_eq!(
+            urls.url_for("file", &[("name", "a b/c?"), ("path", "docs/a b#1.txt")])
+                .as_deref(),
+            Some("/users/a%20b%2Fc%3F/files/docs/a%20b%231.txt")
+        );
+        assert_eq!(
+            urls.url_for("file", &[("name", "café"), ("path", "x")])
+                .as_deref(),
+            Some("/users/caf%C3%A9/files/x")
+        );
+    }
+
+    #[test]
+    fn url_for_without_route_or_param() {
+        let mut router = Router::new();
+        router.add("/users/:id", Method::GET, ok).name("user");
+        let urls = router.urls();
+        assert_eq!(urls.url_for("user", &[]), None);
+        assert_eq!(urls.url_for("user", &[("name", "1")]), None);
+        assert_eq!(urls.url_for("missing", &[("id", "1")]), None);
+    }
+}
@@ -12,12 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::app::App;
+use crate::app::{App, SubApp};
 use crate::context::Context;
-use crate::extract::{FromRequest, IntoResponse};
+use crate::extract::{FromRequest, IntoResponse, MountPrefix, RemoteAddr, RequestId, RoutePattern};
 use crate::factory::{Handler, ServiceFactory};
+use crate::layer::{box_layer, BoxLayer, HttpService};
+use crate::middleware::request_id::request_id;
+use crate::middleware::{catch_panic, Failure, Middleware, Next};
+use crate::mount::ServiceMount;
 use crate::response::HttpResponse;
-use crate::router::Router;
+use crate::router::{normalize_path, ConflictKind, RouteInfo, Router, TrailingSlash, UrlFor};
+use crate::timeout::Timeout;
+use crate::version::Versioned;
 use core::str;
 use hyper::server::conn::AddrStream;
 use hyper::service::make_service_fn;
@@ -26,26 +32,27 @@ use hyper::Body;
 use hyper::Method;
 use hyper::Request;
 use hyper::Server as HyperServer;
+use hyper::Uri;
+use std::borrow::Cow;
 use std::future::Future;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{self, Poll};
+use tower::ServiceExt;
 
 type Response = hyper::Response<hyper::Body>;
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
 /// https://github.com/hyperium/hyper/blob/19f38b3e7febadedbfc558d17fa41baff73c6ecc/src/common/exec.rs#L28-L57
+#[derive(Default)]
 pub enum Executor {
+    #[default]
     Tokio,
     #[cfg(feature = "tokio_io_uring")]
     TokioUring,
 }
 
-impl Default for Executor {
-    fn default() -> Self {
-        Self::Tokio
-    }
-}
-
 #[cfg(feature = "tokio_io_uring")]
 #[derive(Clone)]
 struct TokioUringExecutor;
@@ -64,7 +71,40 @@ where
 pub struct Server {
     addr: SocketAddr,
     router: Router,
+    handle: RouterHandle,
     executor: Executor,
+    layers: Vec<BoxLayer>,
+    global: Global,
+}
+
+/// Swaps the route table of a running `Server`.
+///
+/// Requests that were already routed finish on the table they started with.
+/// Middleware and the timeout set on the `Server` outlive every swap.
+#[derive(Clone, Default)]
+pub struct RouterHandle {
+    table: Arc<RwLock<Arc<Table>>>,
+}
+
+#[derive(Clone)]
+struct RouterService {
+    handle: RouterHandle,
+}
+
+#[derive(Default)]
+struct Table {
+    router: Router,
+    urls: UrlFor,
+    global: Global,
+}
+
+/// Settings of the `Server` around the router, carried over when the router
+/// is swapped.
+#[derive(Clone, Default)]
+struct Global {
+    middleware: Vec<Arc<dyn Middleware>>,
+    timeout: Option<Timeout>,
+    route_table: Option<String>,
 }
 
 impl Server {
@@ -75,7 +115,10 @@ impl Server {
         Self {
             addr,
             router: Router::new(),
+            handle: RouterHandle::default(),
             executor: Executor::default(),
+            layers: Vec::new(),
+            global: Global::default(),
         }
     }
 
@@ -83,51 +126,129 @@ impl Server {
         self.executor = executor;
     }
 
-    pub fn service<F, T, R>(&mut self, path: &str, method: Method, handler: F)
+    pub fn set_trailing_slash(&mut self, policy: TrailingSlash) {
+        self.router.set_trailing_slash(policy);
+    }
+
+    /// Cancel handlers running longer than `timeout`, unless their app or
+    /// route sets one.
+    pub fn set_timeout<T: Into<Timeout>>(&mut self, timeout: T) {
+        self.global.timeout = Some(timeout.into());
+    }
+
+    /// Serve a plain text listing of all routes at `path` (GET), also after
+    /// the router has been swapped.
+    pub fn set_route_table(&mut self, path: &str) {
+        self.global.route_table = Some(String::from(path));
+    }
+
+    pub fn routes(&self) -> impl Iterator<Item = RouteInfo<'_>> {
+        self.router.routes()
+    }
+
+    /// A handle for replacing the routes once the server is running.
+    pub fn handle(&self) -> RouterHandle {
+        self.handle.clone()
+    }
+
+    pub fn service<F, T, R>(&mut self, path: &str, method: Method, handler: F) -> &mut SubApp
     where
         F: Handler<T, R> + Send + Sync + 'static,
         T: FromRequest + Send + Sync + 'static,
         R: Future<Output = HttpResponse> + Send + Sync + 'static,
     {
-        self.router.add(path, method, handler);
+        self.router.add(path, method, handler)
+    }
+
+    /// Run `middleware` around every handler, before app and route middleware.
+    ///
+    /// Services added with `mount_service` are not wrapped.
+    pub fn wrap<M: Middleware>(&mut self, middleware: M) {
+        self.global.middleware.push(Arc::new(middleware));
+    }
+
+    /// Run the whole router, mounted services included, inside a tower layer.
+    ///
+    /// The first layer added is the outermost.
+    pub fn layer<L>(&mut self, layer: L)
+    where
+        L: tower::Layer<HttpService> + Send + Sync + 'static,
+        L::Service: tower::Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+        <L::Service as tower::Service<Request<Body>>>::Future: Send + 'static,
+        <L::Service as tower::Service<Request<Body>>>::Error: Into<Error>,
+    {
+        self.layers.push(box_layer(layer));
     }
 
     pub fn mount(&mut self, mount_point: &str, app: App) {
-        for subapp in app.apps() {
-            self.router.mount(mount_point, subapp);
+        self.router.mount_app(mount_point, app);
+    }
+
+    /// Mount every version of a `Versioned` API at `mount_point`.
+    pub fn mount_versioned(&mut self, mount_point: &str, versioned: Versioned) {
+        self.router.mount_versioned(mount_point, versioned);
+    }
+
+    /// Forward every request under `prefix` to an existing hyper or tower service.
+    pub fn mount_service<S>(&mut self, prefix: &str, service: S) -> &mut ServiceMount
+    where
+        S: hyper::service::Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<Error>,
+    {
+        self.router.mount_service(prefix, service)
+    }
+
+    /// Serve `app` only for requests whose `Host` matches `host`.
+    ///
+    /// `*.example.com` matches any subdomain, available as the `subdomain` path param.
+    pub fn host(&mut self, host: &str, app: App) {
+        self.router.host(host).mount_app("", app);
+    }
+
+    /// The routes and layers as a tower service, for embedding in another
+    /// hyper or tower stack instead of calling `run`.
+    pub fn into_service(self) -> HttpService {
+        self.handle.install(Table::new(self.router, self.global));
+        let mut service = HttpService::new(RouterService {
+            handle: self.handle,
+        });
+        for layer in self.layers.iter().rev() {
+            service = layer(service);
         }
+        service
     }
 
-    pub async fn run(self) {
-        let shared_router = Arc::new(self.router);
-        match self.executor {
+    pub async fn run(mut self) {
+        let addr = self.addr;
+        let executor = std::mem::take(&mut self.executor);
+        let service = self.into_service();
+        match executor {
             Executor::Tokio => {
-                let service = make_service_fn(move |conn: &AddrStream| {
-                    let addr: String = conn.remote_addr().to_string();
-                    let router_capture = shared_router.clone();
-                    async {
-                        Ok::<_, Error>(service_fn(move |req| {
-                            route(router_capture.clone(), addr.clone(), req)
-                        }))
+                let make_service = make_service_fn(move |conn: &AddrStream| {
+                    let remote = RemoteAddr(Some(conn.remote_addr()));
+                    let service = service.clone();
+                    async move {
+                        Ok::<_, Error>(service_fn(move |req| respond(service.clone(), remote, req)))
                     }
                 });
-                let server = HyperServer::bind(&self.addr).serve(service);
-                info!("Listening on http://{}", self.addr);
+                let server = HyperServer::bind(&addr).serve(make_service);
+                info!("Listening on http://{}", addr);
                 let _ = server.await;
             }
             #[cfg(feature = "tokio_io_uring")]
             Executor::TokioUring => {
-                let listener = tokio::net::TcpListener::bind(self.addr)
+                let listener = tokio::net::TcpListener::bind(addr)
                     .await
                     .expect("bind failed");
                 let server = hyper::server::conn::Http::new().with_executor(TokioUringExecutor);
-                while let Ok((stream, addr)) = listener.accept().await {
-                    let router_capture = shared_router.clone();
+                while let Ok((stream, remote)) = listener.accept().await {
+                    let service = service.clone();
                     server
                         .serve_connection(
                             stream,
                             service_fn(move |req| {
-                                route(router_capture.clone(), addr.to_string(), req)
+                                respond(service.clone(), RemoteAddr(Some(remote)), req)
                             }),
                         )
                         .await
@@ -138,15 +259,175 @@ impl Server {
     }
 }
 
-async fn route(router: Arc<Router>, addr: String, req: Request<Body>) -> Result<Response, Error> {
-    info!("{} {} {}", req.method(), req.uri(), addr);
-    let found_handler = router.route(req.uri().path(), req.method());
-    let res = found_handler
-        .handler
-        .new_service()
-        .await
-        .call(Context::new(req, found_handler.params))
-        .await
-        .into_response();
+impl RouterHandle {
+    /// Atomically replace the routes with `router`.
+    ///
+    /// Middleware, the timeout and the route table listing set on the
+    /// `Server` are kept. Everything set on the old router, such as the
+    /// trailing slash policy and virtual hosts, has to be set on `router` as
+    /// well; apps go in with `Router::mount_app` and `Router::mount_versioned`.
+    pub fn swap(&self, router: Router) {
+        let current = self.load();
+        let table = Table::new(router, current.global.clone());
+        self.install(table);
+    }
+
+    fn install(&self, table: Table) {
+        *self.table.write().unwrap() = Arc::new(table);
+    }
+
+    fn load(&self) -> Arc<Table> {
+        self.table.read().unwrap().clone()
+    }
+}
+
+impl Table {
+    fn new(mut router: Router, global: Global) -> Self {
+        if let Some(path) = &global.route_table {
+            let table: String = router.routes().map(|r| format!("{}\n", r)).collect();
+            router.add(path, Method::GET, move || {
+                let table = table.clone();
+                async move {
+                    HttpResponse::Ok()
+                        .header("Content-Type", "text/plain; charset=UTF-8")
+                        .text(&table)
+                }
+            });
+        }
+        for conflict in router.conflicts() {
+            match conflict.kind {
+                ConflictKind::Duplicate => error!("{}", conflict),
+                ConflictKind::Ambiguous => warn!("{}", conflict),
+            }
+        }
+        Self {
+            urls: router.urls(),
+            router,
+            global,
+        }
+    }
+}
+
+impl tower::Service<Request<Body>> for RouterService {
+    type Response = Response;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut task::Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        Box::pin(route(self.handle.load(), req))
+    }
+}
+
+/// Serve one request of a connection, answering errors from layers with a 500.
+async fn respond(
+    service: HttpService,
+    remote: RemoteAddr,
+    mut req: Request<Body>,
+) -> Result<Response, Error> {
+    req.extensions_mut().insert(remote);
+    match service.oneshot(req).await {
+        Ok(res) => Ok(res),
+        Err(e) => {
+            error!("Request failed: {}", e);
+            Ok(HttpResponse::InternalServerError().empty().res())
+        }
+    }
+}
+
+async fn route(table: Arc<Table>, mut req: Request<Body>) -> Result<Response, Error> {
+    let addr = match req.extensions().get::<RemoteAddr>() {
+        Some(RemoteAddr(Some(addr))) => addr.to_string(),
+        _ => String::from("-"),
+    };
+    req.extensions_mut().insert(table.urls.clone());
+    normalize_uri(&mut req);
+    if let Some(mount) = table.router.service_for(&req) {
+        let id = String::from(request_id(&req));
+        info!(
+            "{} {} {}/* {} {}",
+            req.method(),
+            req.uri(),
+            mount.prefix(),
+            addr,
+            id
+        );
+        return match mount.call(req).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                error!(
+                    "Service mounted at {} failed on request {}: {}",
+                    mount.prefix(),
+                    id,
+                    e
+                );
+                Ok(HttpResponse::InternalServerError().empty().res())
+            }
+        };
+    }
+    let found_handler = table.router.route(&req);
+    let pattern = found_handler.pattern().unwrap_or("-");
+    info!(
+        "{} {} {} {} {}",
+        req.method(),
+        req.uri(),
+        pattern,
+        addr,
+        request_id(&req)
+    );
+    if let Some(route) = found_handler.route {
+        let extensions = req.extensions_mut();
+        extensions.insert(RoutePattern(route.pattern.clone()));
+        extensions.insert(MountPrefix(route.prefix.clone()));
+    }
+    let mut middleware = found_handler.middleware;
+    middleware.splice(0..0, table.global.middleware.iter().cloned());
+    let timeout = found_handler
+        .timeout
+        .or_else(|| table.global.timeout.clone());
+    let service = found_handler.handler.new_service().await;
+    let next = Next::new(middleware, service, timeout);
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let id = req.extensions().get::<RequestId>().cloned();
+    let c = Context::new(req, found_handler.params);
+    let mut res = catch_panic(move || next.run(c)).await.into_response();
+    if let Some(failure) = res.extensions_mut().remove::<Failure>() {
+        let id = id.as_ref().map_or("-", |id| id.0.as_str());
+        match failure {
+            Failure::Panicked(message) => {
+                error!(
+                    "{} {} {} {} panicked: {}",
+                    method, uri, pattern, id, message
+                )
+            }
+            Failure::TimedOut(duration) => {
+                warn!(
+                    "{} {} {} {} timed out after {:?}",
+                    method, uri, pattern, id, duration
+                )
+            }
+        }
+    }
     Ok(res)
 }
+
+/// Replace the request path with its normalized form, keeping the query.
+fn normalize_uri(req: &mut Request<Body>) {
+    let path = match normalize_path(req.uri().path()) {
+        Cow::Borrowed(_) => return,
+        Cow::Owned(path) => path,
+    };
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::response::HttpResponse;
+use hyper::body::Bytes;
+use hyper::{Body, Response, StatusCode};
+use std::time::Duration;
+
+/// How long a handler may run before it is cancelled, and the response sent
+/// instead.
+///
+/// Set with `Server::set_timeout`, `App::timeout` or `SubApp::timeout`; the
+/// most specific one applies. Middleware still runs around the timeout
+/// response.
+#[derive(Clone, Debug)]
+pub struct Timeout {
+    duration: Duration,
+    status: StatusCode,
+    body: Bytes,
+}
+
+impl Timeout {
+    /// Answers `503 Service Unavailable` with an empty body.
+    pub const fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            body: Bytes::new(),
+        }
+    }
+
+    /// `503 Service Unavailable` or `504 Gateway Timeout`.
+    pub fn status(&mut self, status: StatusCode) -> &mut Self {
+        self.status = status;
+        self
+    }
+
+    /// A plain text body for the timeout response.
+    pub fn body(&mut self, body: &str) -> &mut Self {
+        self.body = Bytes::copy_from_slice(body.as_bytes());
+        self
+    }
+
+    pub const fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    pub(crate) fn response(&self) -> HttpResponse {
+        let mut res = Response::builder().status(self.status);
+        if !self.body.is_empty() {
+            res = res.header("Content-Type", "text/plain; charset=UTF-8");
+        }
+        HttpResponse::from_builder(res.body(Body::from(self.body.clone())).unwrap())
+    }
+}
+
+impl From<Duration> for Timeout {
+    fn from(duration: Duration) -> Self {
+        Self::new(duration)
+    }
+}
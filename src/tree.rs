@@ -0,0 +1,419 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Radix tree matching paths against route patterns.
+//!
+//! Static text is stored with shared prefixes compressed into single nodes.
+//! `:param` matches one or more characters other than `/`, and `*wildcard`
+//! matches one or more of any character. At every node static children are
+//! tried first, then the param child, then the wildcard child, backtracking
+//! when a branch doesn't lead to an accepted value.
+//!
+//! Backtracking is bounded: positions from which a node is known not to
+//! match are remembered for the rest of the lookup, and a lookup gives up
+//! after `MAX_STEPS` attempts.
+
+use fnv::FnvHashSet;
+
+/// Attempts allowed per lookup before it counts as no match.
+const MAX_STEPS: usize = 10_000;
+
+/// Attempts after which failures are remembered, so that ordinary lookups
+/// don't allocate.
+const MEMO_AFTER: usize = 64;
+
+pub(crate) struct Tree<T> {
+    root: Node<T>,
+}
+
+struct Node<T> {
+    prefix: String,
+    children: Vec<Node<T>>,
+    param: Option<Box<Node<T>>>,
+    wildcard: Option<Box<Node<T>>>,
+    value: Option<T>,
+}
+
+/// State of one lookup.
+struct Search<'p> {
+    params: Vec<&'p str>,
+    /// `(node, rest.len())` pairs known to match nothing.
+    failed: FnvHashSet<(usize, usize)>,
+    /// Calls to `accept`, whose answer may depend on earlier params.
+    accepts: usize,
+    steps: usize,
+}
+
+enum Piece<'a> {
+    Static(&'a str),
+    Param,
+    Wildcard,
+}
+
+impl<T> Tree<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            root: Node::new(String::new()),
+        }
+    }
+
+    /// The value slot for `pattern`. Param and wildcard names are ignored, so
+    /// `/users/:id` and `/users/:name` share a slot.
+    pub(crate) fn entry(&mut self, pattern: &str) -> &mut Option<T> {
+        let mut node = &mut self.root;
+        if !pattern.starts_with('/') {
+            node = node.insert_static("/");
+        }
+        for piece in pieces(pattern) {
+            node = match piece {
+                Piece::Static(s) => node.insert_static(s),
+                Piece::Param => node.param.get_or_insert_with(Default::default),
+                Piece::Wildcard => node.wildcard.get_or_insert_with(Default::default),
+            };
+        }
+        &mut node.value
+    }
+
+    /// Find the highest priority value for `path` that `accept` agrees to,
+    /// given the param and wildcard values in pattern order.
+    pub(crate) fn lookup<'p, F>(&self, path: &'p str, mut accept: F) -> bool
+    where
+        F: FnMut(&T, &[&'p str]) -> bool,
+    {
+        let mut search = Search {
+            params: Vec::new(),
+            failed: FnvHashSet::default(),
+            accepts: 0,
+            steps: 0,
+        };
+        self.root.search(path, &mut search, &mut accept)
+    }
+}
+
+impl<T> Default for Tree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Self::new(String::new())
+    }
+}
+
+impl<T> Node<T> {
+    const fn new(prefix: String) -> Self {
+        Self {
+            prefix,
+            children: Vec::new(),
+            param: None,
+            wildcard: None,
+            value: None,
+        }
+    }
+
+    /// The node reached by following `s` from this node, splitting
+    /// compressed prefixes as needed.
+    fn insert_static(&mut self, s: &str) -> &mut Node<T> {
+        if s.is_empty() {
+            return self;
+        }
+        let first = s.chars().next();
+        let index = match self
+            .children
+            .iter()
+            .position(|c| c.prefix.chars().next() == first)
+        {
+            Some(index) => index,
+            None => {
+                self.children.push(Node::new(String::from(s)));
+                return self.children.last_mut().unwrap();
+            }
+        };
+        let child = &mut self.children[index];
+        let common = common_prefix(&child.prefix, s);
+        if common < child.prefix.len() {
+            let suffix = child.prefix.split_off(common);
+            let mut split = Node::new(suffix);
+            split.children = std::mem::take(&mut child.children);
+            split.param = child.param.take();
+            split.wildcard = child.wildcard.take();
+            split.value = child.value.take();
+            child.children.push(split);
+        }
+        child.insert_static(&s[common..])
+    }
+
+    /// Match `rest`, the part of the path after this node's prefix.
+    fn search<'p, F>(&self, rest: &'p str, search: &mut Search<'p>, accept: &mut F) -> bool
+    where
+        F: FnMut(&T, &[&'p str]) -> bool,
+    {
+        if rest.is_empty() {
+            if let Some(value) = &self.value {
+                search.accepts += 1;
+                if accept(value, &search.params) {
+                    return true;
+                }
+            }
+            return false;
+        }
+
+        let child = self
+            .children
+            .iter()
+            .find_map(|c| Some((c, rest.strip_prefix(c.prefix.as_str())?)));
+        if let Some((child, rest)) = child {
+            if child.search(rest, search, accept) {
+                return true;
+            }
+        }
+
+        if let Some(param) = &self.param {
+            let segment = rest.find('/').unwrap_or(rest.len());
+            // Only a static suffix inside the segment (`:name.json`) needs
+            // shorter candidates; otherwise the param takes the whole segment.
+            let shortest = if param.ends_at_slash() { segment } else { 1 };
+            for end in (shortest..=segment)
+                .rev()
+                .filter(|&i| rest.is_char_boundary(i))
+            {
+                if end == 0 || search.steps > MAX_STEPS {
+                    break;
+                }
+                if param.try_after(&rest[..end], &rest[end..], search, accept) {
+                    return true;
+                }
+            }
+        }
+
+        if let Some(wildcard) = &self.wildcard {
+            let shortest = if wildcard.is_leaf() { rest.len() } else { 1 };
+            for end in (shortest..=rest.len())
+                .rev()
+                .filter(|&i| rest.is_char_boundary(i))
+            {
+                if search.steps > MAX_STEPS {
+                    break;
+                }
+                if wildcard.try_after(&rest[..end], &rest[end..], search, accept) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Match `rest` after taking `value` as this param or wildcard node's value.
+    ///
+    /// Several splits of a segment can reach this node at the same position,
+    /// so a failure that didn't involve `accept` is remembered.
+    fn try_after<'p, F>(
+        &self,
+        value: &'p str,
+        rest: &'p str,
+        search: &mut Search<'p>,
+        accept: &mut F,
+    ) -> bool
+    where
+        F: FnMut(&T, &[&'p str]) -> bool,
+    {
+        search.steps += 1;
+        let key = (self as *const Self as usize, rest.len());
+        if search.steps > MAX_STEPS || search.failed.contains(&key) {
+            return false;
+        }
+        let accepts = search.accepts;
+        search.params.push(value);
+        if self.search(rest, search, accept) {
+            return true;
+        }
+        search.params.pop();
+        if search.accepts == accepts && (MEMO_AFTER..=MAX_STEPS).contains(&search.steps) {
+            search.failed.insert(key);
+        }
+        false
+    }
+
+    fn ends_at_slash(&self) -> bool {
+        self.param.is_none()
+            && self.wildcard.is_none()
+            && self.children.iter().all(|c| c.prefix.starts_with('/'))
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty() && self.param.is_none() && self.wildcard.is_none()
+    }
+}
+
+/// Split a pattern into static text, params and wildcards. Names end at
+/// `/` or `.`, as in `/files/:name.:ext`.
+fn pieces(pattern: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let bytes = pattern.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let segment_start = i == 0 || bytes[i - 1] == b'/' || bytes[i - 1] == b'.';
+        if segment_start && (bytes[i] == b':' || bytes[i] == b'*') {
+            if start < i {
+                pieces.push(Piece::Static(&pattern[start..i]));
+            }
+            pieces.push(if bytes[i] == b':' {
+                Piece::Param
+            } else {
+                Piece::Wildcard
+            });
+            i += 1;
+            while i < bytes.len() && bytes[i] != b'/' && bytes[i] != b'.' {
+                i += 1;
+            }
+            start = i;
+        } else {
+            i += 1;
+        }
+    }
+    if start < bytes.len() {
+        pieces.push(Piece::Static(&pattern[start..]));
+    }
+    pieces
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or_else(|| a.len().min(b.len()), |((i, _), _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tree;
+    use std::time::{Duration, Instant};
+
+    fn build(patterns: &[&str]) -> Tree<usize> {
+        let mut tree = Tree::new();
+        for (i, pattern) in patterns.iter().enumerate() {
+            *tree.entry(pattern) = Some(i);
+        }
+        tree
+    }
+
+    /// The index of the matched pattern and its param values.
+    fn lookup<'p>(tree: &Tree<usize>, path: &'p str) -> Option<(usize, Vec<&'p str>)> {
+        let mut found = None;
+        tree.lookup(path, |&i, params| {
+            found = Some((i, params.to_vec()));
+            true
+        });
+        found
+    }
+
+    #[test]
+    fn static_before_param_before_wildcard() {
+        let tree = build(&["/a/*rest", "/a/:x", "/a/b"]);
+        assert_eq!(lookup(&tree, "/a/b"), Some((2, vec![])));
+        assert_eq!(lookup(&tree, "/a/c"), Some((1, vec!["c"])));
+        assert_eq!(lookup(&tree, "/a/c/d"), Some((0, vec!["c/d"])));
+        assert_eq!(lookup(&tree, "/a/"), None);
+    }
+
+    #[test]
+    fn backtracks_out_of_static_branch() {
+        let tree = build(&["/a/b/c", "/a/:x/d"]);
+        assert_eq!(lookup(&tree, "/a/b/c"), Some((0, vec![])));
+        assert_eq!(lookup(&tree, "/a/b/d"), Some((1, vec!["b"])));
+
+        let tree = build(&["/a/:x/c", "/a/*rest"]);
+        assert_eq!(lookup(&tree, "/a/b/c"), Some((0, vec!["b"])));
+        assert_eq!(lookup(&tree, "/a/b/d"), Some((1, vec!["b/d"])));
+    }
+
+    #[test]
+    fn rejected_value_falls_through() {
+        let tree = build(&["/a/b", "/a/:x"]);
+        let mut found = None;
+        tree.lookup("/a/b", |&i, params| {
+            found = Some((i, params.to_vec()));
+            i != 0
+        });
+        assert_eq!(found, Some((1, vec!["b"])));
+    }
+
+    #[test]
+    fn param_split_at_dot() {
+        let tree = build(&["/files/:name.:ext"]);
+        assert_eq!(lookup(&tree, "/files/a.txt"), Some((0, vec!["a", "txt"])));
+        assert_eq!(
+            lookup(&tree, "/files/a.b.txt"),
+            Some((0, vec!["a.b", "txt"]))
+        );
+        assert_eq!(lookup(&tree, "/files/readme"), None);
+        assert_eq!(lookup(&tree, "/files/a./b"), None);
+    }
+
+    #[test]
+    fn wildcard_with_static_suffix() {
+        let tree = build(&["/static/*path/edit"]);
+        assert_eq!(lookup(&tree, "/static/a/edit"), Some((0, vec!["a"])));
+        assert_eq!(lookup(&tree, "/static/a/b/edit"), Some((0, vec!["a/b"])));
+        assert_eq!(lookup(&tree, "/static/edit"), None);
+        assert_eq!(lookup(&tree, "/static/a/b"), None);
+    }
+
+    #[test]
+    fn insert_splits_shared_prefixes() {
+        let tree = build(&["/abc", "/abd", "/ab"]);
+        let node = &tree.root.children[0];
+        assert_eq!(tree.root.children.len(), 1);
+        assert_eq!(node.prefix, "/ab");
+        assert_eq!(node.value, Some(2));
+        let children: Vec<_> = node.children.iter().map(|c| c.prefix.as_str()).collect();
+        assert_eq!(children, ["c", "d"]);
+
+        assert_eq!(lookup(&tree, "/abc"), Some((0, vec![])));
+        assert_eq!(lookup(&tree, "/abd"), Some((1, vec![])));
+        assert_eq!(lookup(&tree, "/ab"), Some((2, vec![])));
+        assert_eq!(lookup(&tree, "/a"), None);
+        assert_eq!(lookup(&tree, "/abcd"), None);
+    }
+
+    #[test]
+    fn dotted_params_backtrack_in_bounded_time() {
+        let tree = build(&["/f/:a.:b.:c.json"]);
+        let path = format!("/f/{}", ".".repeat(5000));
+        let start = Instant::now();
+        assert_eq!(lookup(&tree, &path), None);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(
+            lookup(&tree, "/f/x.y.z.json"),
+            Some((0, vec!["x", "y", "z"]))
+        );
+    }
+
+    #[test]
+    fn rejected_values_are_retried_with_other_params() {
+        let tree = build(&["/:a.:b.json"]);
+        let rest = vec!["q"; 30].join(".");
+        let path = format!("/p.{}.json", rest);
+        let mut found = None;
+        tree.lookup(&path, |_, params| {
+            found = Some(params.to_vec());
+            params[0] == "p"
+        });
+        assert_eq!(found, Some(vec!["p", rest.as_str()]));
+    }
+}
@@ -0,0 +1,268 @@
+// Copyright 2021 Black Tea Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::app::{App, SubApp};
+use crate::context::Context;
+use crate::factory::{factory, BoxFuture, BoxService, BoxServiceFactory, Service, ServiceFactory};
+use crate::response::HttpResponse;
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::{Body, Method, Request};
+use mime::Mime;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Serves several versions of an API under one prefix, picking the `App`
+/// from a version header or the `Accept` media type.
+///
+/// The version is read from the `Api-Version` header (configurable), then
+/// from `Accept: application/vnd.<vendor>.v<N>+json` or a `version` media type
+/// parameter, and otherwise falls back to the default version. Requests for
+/// a version that doesn't exist, or for none without a default, are answered
+/// with `400 Bad Request`.
+pub struct Versioned {
+    vendor: Option<String>,
+    header: HeaderName,
+    default: Option<String>,
+    versions: Vec<Version>,
+}
+
+pub struct Version {
+    name: String,
+    app: App,
+    /// Unix time of the deprecation.
+    deprecated: Option<u64>,
+    sunset: Option<String>,
+}
+
+struct Selector {
+    vendor: Option<String>,
+    header: HeaderName,
+    default: Option<String>,
+    versions: Vec<String>,
+}
+
+impl Versioned {
+    pub fn new() -> Self {
+        Self {
+            vendor: None,
+            header: HeaderName::from_static("api-version"),
+            default: None,
+            versions: Vec::new(),
+        }
+    }
+
+    /// Recognize `application/vnd.<vendor>.v<N>` media types in `Accept`.
+    pub fn vendor(&mut self, vendor: &str) -> &mut Self {
+        self.vendor = Some(String::from(vendor));
+        self
+    }
+
+    /// Read the version from `name` instead of `Api-Version`.
+    pub fn header(&mut self, name: &str) -> &mut Self {
+        self.header = HeaderName::from_bytes(name.as_bytes()).expect("Invalid header name.");
+        self
+    }
+
+    /// The version served when a request doesn't ask for one.
+    pub fn default_version(&mut self, version: &str) -> &mut Self {
+        self.default = Some(String::from(version));
+        self
+    }
+
+    pub fn version(&mut self, version: &str, app: App) -> &mut Version {
+        self.versions.push(Version {
+            name: String::from(version),
+            app,
+            deprecated: None,
+            sunset: None,
+        });
+        self.versions.last_mut().unwrap()
+    }
+
+    /// The routes of every version, guarded and decorated for mounting.
+    pub(crate) fn apps(self) -> Vec<SubApp> {
+        let selector = Arc::new(Selector {
+            vendor: self.vendor,
+            header: self.header,
+            default: self.default,
+            versions: self.versions.iter().map(|v| v.name.clone()).collect(),
+        });
+        let vary = format!("Accept, {}", selector.header);
+        let vary = Arc::new(vec![(header::VARY, HeaderValue::from_str(&vary).unwrap())]);
+        let mut routes: Vec<(String, Method)> = Vec::new();
+        let mut apps = Vec::new();
+        for version in self.versions {
+            let headers = Arc::new(version.headers(&selector.header));
+            for mut subapp in version.app.apps() {
+                let selector = selector.clone();
+                let name = version.name.clone();
+                subapp.guard(move |req: &Request<Body>| {
+                    selector.requested(req).as_deref() == Some(name.as_str())
+                });
+                subapp.handler = factory(WithHeaders {
+                    inner: subapp.handler,
+                    headers: headers.clone(),
+                });
+                let route = (subapp.path.clone(), subapp.method.clone());
+                if !routes.contains(&route) {
+                    routes.push(route);
+                }
+                apps.push(subapp);
+            }
+        }
+        for (path, method) in routes {
+            let mut subapp = SubApp::new(path, method, unsupported_version);
+            let selector = selector.clone();
+            subapp.guard(move |req: &Request<Body>| !selector.supports(req));
+            subapp.handler = factory(WithHeaders {
+                inner: subapp.handler,
+                headers: vary.clone(),
+            });
+            apps.push(subapp);
+        }
+        apps
+    }
+}
+
+impl Default for Versioned {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Version {
+    /// Mark responses of this version as deprecated since `at`, sent as
+    /// `Deprecation: @<unix seconds>` (RFC 9745).
+    pub fn deprecated(&mut self, at: SystemTime) -> &mut Self {
+        let at = at
+            .duration_since(UNIX_EPOCH)
+            .expect("Deprecation date before 1970.");
+        self.deprecated = Some(at.as_secs());
+        self
+    }
+
+    /// Announce removal of this version at `http_date` with a `Sunset` header.
+    /// Usually paired with `deprecated`.
+    pub fn sunset(&mut self, http_date: &str) -> &mut Self {
+        self.sunset = Some(String::from(http_date));
+        self
+    }
+
+    fn headers(&self, version_header: &HeaderName) -> Vec<(HeaderName, HeaderValue)> {
+        let vary = format!("Accept, {}", version_header);
+        let mut headers = vec![
+            (header::VARY, HeaderValue::from_str(&vary).unwrap()),
+            (
+                version_header.clone(),
+                HeaderValue::from_str(&self.name).expect("Invalid API version."),
+            ),
+        ];
+        if let Some(at) = self.deprecated {
+            headers.push((
+                HeaderName::from_static("deprecation"),
+                HeaderValue::from_str(&format!("@{}", at)).unwrap(),
+            ));
+        }
+        if let Some(sunset) = &self.sunset {
+            headers.push((
+                HeaderName::from_static("sunset"),
+                HeaderValue::from_str(sunset).expect("Invalid sunset date."),
+            ));
+        }
+        headers
+    }
+}
+
+impl Selector {
+    fn requested(&self, req: &Request<Body>) -> Option<String> {
+        let headers = req.headers();
+        if let Some(version) = headers.get(&self.header).and_then(|v| v.to_str().ok()) {
+            return Some(String::from(version.trim()));
+        }
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        for range in accept
+            .split(',')
+            .filter_map(|r| r.trim().parse::<Mime>().ok())
+        {
+            if let Some(version) = range.get_param("version") {
+                return Some(String::from(version.as_str()));
+            }
+            if let Some(vendor) = &self.vendor {
+                let prefix = format!("vnd.{}.v", vendor);
+                if let Some(version) = range.subtype().as_str().strip_prefix(&prefix) {
+                    return Some(String::from(version));
+                }
+            }
+        }
+        self.default.clone()
+    }
+
+    /// Whether `req` asks for one of the registered versions.
+    fn supports(&self, req: &Request<Body>) -> bool {
+        self.requested(req)
+            .is_some_and(|version| self.versions.contains(&version))
+    }
+}
+
+async fn unsupported_version() -> HttpResponse {
+    HttpResponse::BadRequest().text("Unsupported API version")
+}
+
+/// Adds fixed headers to every response of the wrapped handler.
+struct WithHeaders {
+    inner: BoxServiceFactory<HttpResponse>,
+    headers: Arc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+struct WithHeadersService {
+    inner: BoxService<HttpResponse>,
+    headers: Arc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl ServiceFactory for WithHeaders {
+    type Response = HttpResponse;
+    type Service = WithHeadersService;
+    type Future = BoxFuture<Self::Service>;
+
+    fn new_service(&self) -> Self::Future {
+        let inner = self.inner.new_service();
+        let headers = self.headers.clone();
+        Box::pin(async move {
+            WithHeadersService {
+                inner: inner.await,
+                headers,
+            }
+        })
+    }
+}
+
+impl Service for WithHeadersService {
+    type Response = HttpResponse;
+    type Future = BoxFuture<HttpResponse>;
+
+    fn call(&self, c: Context) -> Self::Future {
+        let res = self.inner.call(c);
+        let headers = self.headers.clone();
+        Box::pin(async move {
+            let mut res = res.await.res();
+            for (name, value) in headers.iter() {
+                res.headers_mut().append(name.clone(), value.clone());
+            }
+            HttpResponse::from_builder(res)
+        })
+    }
+}